@@ -0,0 +1,410 @@
+use crate::groups::DuplicateGroup;
+use crate::lossless::authenticity_rank;
+use crate::{AudioFile, file_modified_secs};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Decision {
+    Keep,
+    Delete,
+}
+
+// What to do with a duplicate once the file to keep is chosen
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DupeAction {
+    Remove,
+    Hardlink,
+    Symlink,
+    Move(PathBuf),
+}
+
+impl DupeAction {
+    // "rm", "ln", "ln-s" or "mv" (the latter needs a target directory)
+    pub fn parse(name: &str, move_to: Option<&Path>) -> Result<Self, String> {
+        match name.trim() {
+            "rm" => Ok(DupeAction::Remove),
+            "ln" => Ok(DupeAction::Hardlink),
+            "ln-s" => Ok(DupeAction::Symlink),
+            "mv" => move_to
+                .map(|dir| DupeAction::Move(dir.to_path_buf()))
+                .ok_or_else(|| "action \"mv\" needs a target directory".to_string()),
+            other => Err(format!(
+                "unknown action \"{other}\" (use rm, ln, ln-s or mv)"
+            )),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            DupeAction::Remove => "rm (delete duplicates)".to_string(),
+            DupeAction::Hardlink => {
+                "ln (replace duplicates with hardlinks to the kept file)".to_string()
+            }
+            DupeAction::Symlink => {
+                "ln-s (replace duplicates with symlinks to the kept file)".to_string()
+            }
+            DupeAction::Move(dir) => format!("mv (move duplicates under {})", dir.display()),
+        }
+    }
+}
+
+// How to pick the single file to keep in a group when the user did not choose by hand
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeepPolicy {
+    First,
+    ShortestPath,
+    Oldest,
+    Newest,
+    Largest,
+    Smallest,
+    HighestQuality,
+}
+
+impl KeepPolicy {
+    pub const ALL: [KeepPolicy; 7] = [
+        KeepPolicy::First,
+        KeepPolicy::ShortestPath,
+        KeepPolicy::Oldest,
+        KeepPolicy::Newest,
+        KeepPolicy::Largest,
+        KeepPolicy::Smallest,
+        KeepPolicy::HighestQuality,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeepPolicy::First => "first",
+            KeepPolicy::ShortestPath => "shortest-path",
+            KeepPolicy::Oldest => "oldest",
+            KeepPolicy::Newest => "newest",
+            KeepPolicy::Largest => "largest",
+            KeepPolicy::Smallest => "smallest",
+            KeepPolicy::HighestQuality => "highest-quality",
+        }
+    }
+
+    // Cycle order used by the review UI
+    pub fn next(&self) -> KeepPolicy {
+        let idx = Self::ALL.iter().position(|p| p == self).unwrap_or(0);
+        Self::ALL[(idx + 1) % Self::ALL.len()]
+    }
+
+    // Index of the file to keep. Ties fall back to path order so the choice is deterministic.
+    // Reference files stay whatever happens, so a group containing one keeps one of them.
+    pub fn choose(&self, files: &[AudioFile]) -> usize {
+        let by_path = |a: &usize, b: &usize| files[*a].file_path.cmp(&files[*b].file_path);
+        let any_reference = files.iter().any(|f| f.reference);
+        let indices = (0..files.len()).filter(|&idx| !any_reference || files[idx].reference);
+        let picked = match self {
+            KeepPolicy::First => indices.min_by(by_path),
+            KeepPolicy::ShortestPath => indices.min_by(|a, b| {
+                files[*a]
+                    .file_path
+                    .len()
+                    .cmp(&files[*b].file_path.len())
+                    .then_with(|| by_path(a, b))
+            }),
+            KeepPolicy::Oldest => indices.min_by(|a, b| {
+                files[*a]
+                    .modified_secs
+                    .cmp(&files[*b].modified_secs)
+                    .then_with(|| by_path(a, b))
+            }),
+            KeepPolicy::Newest => indices.min_by(|a, b| {
+                files[*b]
+                    .modified_secs
+                    .cmp(&files[*a].modified_secs)
+                    .then_with(|| by_path(a, b))
+            }),
+            KeepPolicy::Largest => indices.min_by(|a, b| {
+                files[*b]
+                    .file_size
+                    .cmp(&files[*a].file_size)
+                    .then_with(|| by_path(a, b))
+            }),
+            KeepPolicy::Smallest => indices.min_by(|a, b| {
+                files[*a]
+                    .file_size
+                    .cmp(&files[*b].file_size)
+                    .then_with(|| by_path(a, b))
+            }),
+            // The genuine master first (not a lossy transcode or padded copy), then the effective
+            // bit depth and rate, then the richer container (FLAC carries tags and checksums),
+            // then tags
+            KeepPolicy::HighestQuality => indices.min_by(|a, b| {
+                quality_rank(&files[*b])
+                    .cmp(&quality_rank(&files[*a]))
+                    .then_with(|| by_path(a, b))
+            }),
+        };
+        picked.unwrap_or(0)
+    }
+}
+
+fn quality_rank(file: &AudioFile) -> (u8, u32, u32, bool, usize) {
+    (
+        authenticity_rank(file),
+        file.content_bit_depth(),
+        file.content_sample_rate(),
+        file.format() == "FLAC",
+        file.tags.len(),
+    )
+}
+
+impl std::str::FromStr for KeepPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        KeepPolicy::ALL
+            .iter()
+            .find(|p| p.name() == s.trim())
+            .copied()
+            .ok_or_else(|| {
+                let names: Vec<&str> = KeepPolicy::ALL.iter().map(|p| p.name()).collect();
+                format!("unknown keep policy \"{s}\" (use {})", names.join(", "))
+            })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlannedFile {
+    pub file: AudioFile,
+    pub decision: Decision,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupPlan {
+    pub files: Vec<PlannedFile>,
+    // Set once the user or a policy made a decision for this group
+    pub reviewed: bool,
+    // Index of the file the policy or the user picked to keep; other kept files (such as
+    // references) are not link targets
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chosen: Option<usize>,
+}
+
+impl GroupPlan {
+    pub fn from_group(group: &DuplicateGroup) -> Self {
+        GroupPlan {
+            files: group
+                .files
+                .iter()
+                .map(|file| PlannedFile {
+                    file: file.clone(),
+                    decision: Decision::Keep,
+                })
+                .collect(),
+            reviewed: false,
+            chosen: None,
+        }
+    }
+
+    pub fn apply_policy(&mut self, policy: KeepPolicy) {
+        let files: Vec<AudioFile> = self.files.iter().map(|p| p.file.clone()).collect();
+        let keep = policy.choose(&files);
+        for (idx, planned) in self.files.iter_mut().enumerate() {
+            planned.decision = if idx == keep || planned.file.reference {
+                Decision::Keep
+            } else {
+                Decision::Delete
+            };
+        }
+        self.reviewed = true;
+        self.chosen = Some(keep);
+    }
+
+    // The picked file while it is still kept, else the first kept file in path order
+    pub fn kept(&self) -> Option<&AudioFile> {
+        self.chosen
+            .and_then(|idx| self.files.get(idx))
+            .filter(|p| p.decision == Decision::Keep)
+            .or_else(|| self.files.iter().find(|p| p.decision == Decision::Keep))
+            .map(|p| &p.file)
+    }
+
+    pub fn reclaimable_bytes(&self) -> u64 {
+        self.files
+            .iter()
+            .filter(|p| p.decision == Decision::Delete)
+            .map(|p| p.file.file_size)
+            .sum()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ActionPlan {
+    pub groups: Vec<GroupPlan>,
+}
+
+// One line of the JSONL action journal, written right after each action succeeds
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub timestamp: u64,
+    pub action: String,
+    pub path: String,
+    pub kept: String,
+    pub file_size: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct ExecutionSummary {
+    pub deleted: usize,
+    pub reclaimed_bytes: u64,
+    pub errors: Vec<String>,
+}
+
+impl ActionPlan {
+    pub fn new(groups: &[DuplicateGroup]) -> Self {
+        ActionPlan {
+            groups: groups.iter().map(GroupPlan::from_group).collect(),
+        }
+    }
+
+    // Apply a policy to every group nobody has looked at yet
+    pub fn apply_policy_to_unreviewed(&mut self, policy: KeepPolicy) {
+        for group in self.groups.iter_mut().filter(|g| !g.reviewed) {
+            group.apply_policy(policy);
+        }
+    }
+
+    pub fn pending_deletes(&self) -> usize {
+        self.groups
+            .iter()
+            .flat_map(|g| &g.files)
+            .filter(|p| p.decision == Decision::Delete)
+            .count()
+    }
+
+    pub fn reclaimable_bytes(&self) -> u64 {
+        self.groups.iter().map(GroupPlan::reclaimable_bytes).sum()
+    }
+
+    pub fn save_json(&self, path: &Path) -> std::io::Result<()> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(&file, self)?;
+        file.sync_all()
+    }
+
+    // Delete every file marked Delete, appending one journal line per removed file.
+    // A file is only touched if it and the kept file still have the size and mtime recorded
+    // during the scan, and a group where nothing is kept is refused outright.
+    pub fn execute(&self, journal_path: &Path) -> std::io::Result<ExecutionSummary> {
+        let mut journal = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(journal_path)?;
+        let mut summary = ExecutionSummary::default();
+
+        for group in &self.groups {
+            let Some(kept) = group.kept() else {
+                summary.errors.push(format!(
+                    "Refusing to delete every copy of {}",
+                    group.files[0].file.file_path
+                ));
+                continue;
+            };
+            let has_deletes = group.files.iter().any(|p| p.decision == Decision::Delete);
+            // The copies are only redundant while the kept file is still what was scanned
+            if has_deletes && let Err(reason) = check_unchanged(kept) {
+                summary.errors.push(format!(
+                    "Skipping the duplicates of {}: kept file is not as scanned ({reason})",
+                    kept.file_path
+                ));
+                continue;
+            }
+
+            for planned in group
+                .files
+                .iter()
+                .filter(|p| p.decision == Decision::Delete)
+            {
+                let file = &planned.file;
+                if file.reference {
+                    summary
+                        .errors
+                        .push(format!("Skipping {}: reference file", file.file_path));
+                    continue;
+                }
+                if let Err(reason) = check_unchanged(file) {
+                    summary
+                        .errors
+                        .push(format!("Skipping {}: {reason}", file.file_path));
+                    continue;
+                }
+                if let Err(err) = std::fs::remove_file(&file.file_path) {
+                    summary
+                        .errors
+                        .push(format!("Failed to delete {}: {err}", file.file_path));
+                    continue;
+                }
+
+                summary.deleted += 1;
+                summary.reclaimed_bytes += file.file_size;
+                let entry = JournalEntry {
+                    timestamp: now_secs(),
+                    action: "delete".to_string(),
+                    path: file.file_path.clone(),
+                    kept: kept.file_path.clone(),
+                    file_size: file.file_size,
+                };
+                serde_json::to_writer(&mut journal, &entry)?;
+                writeln!(journal)?;
+            }
+        }
+
+        journal.sync_all()?;
+        Ok(summary)
+    }
+}
+
+// Guard against acting on a file that was replaced or edited after it was analyzed
+pub fn check_unchanged(file: &AudioFile) -> Result<(), String> {
+    let metadata = std::fs::metadata(&file.file_path).map_err(|e| e.to_string())?;
+    if metadata.len() != file.file_size {
+        return Err(format!(
+            "size changed ({} -> {})",
+            file.file_size,
+            metadata.len()
+        ));
+    }
+    let modified = file_modified_secs(&metadata);
+    if modified != file.modified_secs {
+        return Err(format!(
+            "modified time changed ({} -> {modified})",
+            file.modified_secs
+        ));
+    }
+    Ok(())
+}
+
+// Map of every path removed by a journaled action to the file that was kept in its place
+pub fn read_journal(path: &Path) -> std::io::Result<HashMap<String, String>> {
+    let content = std::fs::read_to_string(path)?;
+    let mut replaced = HashMap::new();
+    for (idx, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: JournalEntry = serde_json::from_str(line).map_err(|err| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{}:{}: {err}", path.display(), idx + 1),
+            )
+        })?;
+        replaced.insert(entry.path, entry.kept);
+    }
+    Ok(replaced)
+}
+
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
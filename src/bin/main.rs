@@ -1,10 +1,26 @@
+use clap::ArgMatches;
 use clap::{Arg, ArgAction, Command, ValueHint, crate_version, value_parser};
-use ctrlc;
-use fadupes::{AudioFile, ResumeCache, SizeFilter, parse_size_filter};
+use fadupes::actions::{ActionPlan, DupeAction, KeepPolicy, read_journal};
+use fadupes::cache::{DiskState, VerifyOutcome};
+use fadupes::events::{EventSink, ScanEvent};
+use fadupes::groups::{
+    DuplicateGroup, MatchOptions, find_duplicate_groups, mark_reference, retain_across_roots,
+};
+use fadupes::overlap;
+use fadupes::playlists::{relink_playlists, write_group_playlists};
+use fadupes::report::{OutputFormat, Report};
+use fadupes::run::{RunInfo, RunLog};
+use fadupes::script::write_script;
+use fadupes::store::StateFormat;
+use fadupes::tui::{ReviewOutcome, review};
+use fadupes::{
+    AudioFile, CacheValidation, ResumeCache, ScanError, ScanOptions, SizeFilter, format_size_bytes,
+    parse_size_filter,
+};
 use rayon::prelude::*;
-use std::collections::{HashMap, HashSet};
-use std::io::Write;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashSet};
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 fn main() {
@@ -12,6 +28,211 @@ fn main() {
         .version(crate_version!())
         .author("menfou")
         .about("Compares audio files in a given directory or multiple inputs and identifies identical files")
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
+        .subcommand(
+            Command::new("relink-playlists")
+                .about("Rewrite M3U/M3U8/PLS entries pointing at removed duplicates to the kept file")
+                .arg(
+                    Arg::new("input")
+                        .short('i')
+                        .long("input")
+                        .help("Directories searched for playlists")
+                        .required(true)
+                        .num_args(1..)
+                        .value_hint(ValueHint::DirPath)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("journal")
+                        .long("journal")
+                        .value_name("PATH")
+                        .value_hint(ValueHint::FilePath)
+                        .help("Action journal to read (default: fadupes_journal.jsonl)")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("dry_run")
+                        .long("dry-run")
+                        .action(ArgAction::SetTrue)
+                        .help("Only report what would be rewritten"),
+                ),
+        )
+        .subcommand(
+            Command::new("report")
+                .about("Report duplicate groups from the state file without rescanning")
+                .arg(
+                    Arg::new("state_file")
+                        .long("state")
+                        .visible_alias("state-file")
+                        .value_name("FILE")
+                        .value_hint(ValueHint::FilePath)
+                        .help("State file of a previous scan (default: fadupes_state.bin)")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("state_format")
+                        .long("state-format")
+                        .value_name("FORMAT")
+                        .help("State file format: binary, json or sqlite (default: from the extension)")
+                        .value_parser(value_parser!(StateFormat)),
+                )
+                .arg(wait_lock_arg())
+                .arg(
+                    Arg::new("input")
+                        .short('i')
+                        .long("input")
+                        .num_args(1..)
+                        .value_hint(ValueHint::DirPath)
+                        .help("Only use cached files under these paths")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("check_stale")
+                        .long("check-stale")
+                        .action(ArgAction::SetTrue)
+                        .help("Skip entries whose file is missing or changed on disk since it was scanned"),
+                )
+                .args(group_output_args()),
+        )
+        .subcommand(
+            Command::new("watch")
+                .about("Scan, then keep watching the inputs and report duplicates as new files arrive (Linux)")
+                .arg(
+                    Arg::new("input")
+                        .short('i')
+                        .long("input")
+                        .help("Directories to scan and watch")
+                        .required(true)
+                        .num_args(1..)
+                        .value_hint(ValueHint::DirPath)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("state_file")
+                        .long("state-file")
+                        .value_hint(ValueHint::FilePath)
+                        .help("Path to the resume state file (default: fadupes_state.bin)")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("state_format")
+                        .long("state-format")
+                        .value_name("FORMAT")
+                        .help("State file format: binary, json or sqlite (default: from the extension)")
+                        .value_parser(value_parser!(StateFormat)),
+                )
+                .arg(wait_lock_arg())
+                .arg(
+                    Arg::new("nosym")
+                        .long("nosym")
+                        .action(ArgAction::SetTrue)
+                        .help("Ignore symlinks instead of following them during the initial scan"),
+                )
+                .arg(
+                    Arg::new("ignore_size")
+                        .long("ignore-size")
+                        .value_name("EXPR")
+                        .help(r#"Ignore files by size. Examples: "<3MB", ">800MB", "3MB..800MB""#),
+                )
+                .arg(
+                    Arg::new("events")
+                        .long("events")
+                        .value_name("FORMAT")
+                        .help("Stream scan events to stdout (ndjson) instead of printing groups")
+                        .value_parser(["ndjson"]),
+                )
+                .arg(
+                    Arg::new("error_log")
+                        .long("error-log")
+                        .value_name("PATH")
+                        .value_hint(ValueHint::FilePath)
+                        .help("Processing errors log, created on the first error (default: identical_files_errors.log)")
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        )
+        .subcommand(
+            Command::new("cache")
+                .about("Inspect and maintain the resume state")
+                .subcommand_required(true)
+                .arg(
+                    Arg::new("state_file")
+                        .long("state-file")
+                        .global(true)
+                        .value_hint(ValueHint::FilePath)
+                        .help("Path to the resume state file (default: fadupes_state.bin)")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("state_format")
+                        .long("state-format")
+                        .global(true)
+                        .value_name("FORMAT")
+                        .help("State file format: binary, json or sqlite (default: from the extension)")
+                        .value_parser(value_parser!(StateFormat)),
+                )
+                .arg(wait_lock_arg().global(true))
+                .subcommand(Command::new("stats").about("Summarize the cached entries"))
+                .subcommand(
+                    Command::new("prune")
+                        .about("Remove entries for files that no longer exist")
+                        .arg(
+                            Arg::new("dry_run")
+                                .long("dry-run")
+                                .action(ArgAction::SetTrue)
+                                .help("Only list what would be removed"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("list")
+                        .about("List cached entries, optionally only those under a path prefix")
+                        .arg(Arg::new("prefix").value_name("PREFIX")),
+                )
+                .subcommand(
+                    Command::new("export")
+                        .about("Export the entries as JSON (importable) or CSV")
+                        .arg(
+                            Arg::new("format")
+                                .long("format")
+                                .value_name("FORMAT")
+                                .help("json or csv")
+                                .default_value("json")
+                                .value_parser(["json", "csv"]),
+                        )
+                        .arg(
+                            Arg::new("output")
+                                .short('o')
+                                .long("output")
+                                .value_name("PATH")
+                                .value_hint(ValueHint::FilePath)
+                                .help("Write to a file instead of stdout")
+                                .value_parser(value_parser!(PathBuf)),
+                        ),
+                )
+                .subcommand(
+                    Command::new("import")
+                        .about("Merge another state file into this one (newer mtime wins)")
+                        .arg(
+                            Arg::new("file")
+                                .value_name("FILE")
+                                .required(true)
+                                .value_hint(ValueHint::FilePath)
+                                .value_parser(value_parser!(PathBuf)),
+                        ),
+                )
+                .subcommand(
+                    Command::new("verify")
+                        .about("Re-decode a sample of entries and compare with the cached analysis")
+                        .arg(
+                            Arg::new("sample")
+                                .long("sample")
+                                .value_name("N")
+                                .help("Number of entries to check, 0 for all")
+                                .default_value("25")
+                                .value_parser(value_parser!(usize)),
+                        ),
+                ),
+        )
         .arg(
             Arg::new("input")
                 .short('i')
@@ -28,6 +249,12 @@ fn main() {
                 .action(ArgAction::SetTrue)
                 .help("Skip files whose byte size is unique (faster, but may miss dupes)"),
         )
+        .arg(
+            Arg::new("overlaps")
+                .long("overlaps")
+                .action(ArgAction::SetTrue)
+                .help("Also look for tracks contained in mixes, live sets or un-split images (decodes files again)"),
+        )
         .arg(
             Arg::new("nolist")
                 .long("nolist")
@@ -38,7 +265,39 @@ fn main() {
             Arg::new("state_file")
                 .long("state-file")
                 .value_hint(ValueHint::FilePath)
-                .help("Path to the resume state file (default: fadupes_state.json)")
+                .help("Path to the resume state file (default: fadupes_state.bin)")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("state_format")
+                .long("state-format")
+                .value_name("FORMAT")
+                .help("State file format: binary, json or sqlite (default: from the extension, .json is json, .db/.sqlite is sqlite)")
+                .value_parser(value_parser!(StateFormat)),
+        )
+        .arg(wait_lock_arg())
+        .arg(
+            Arg::new("cache_validate")
+                .long("cache-validate")
+                .value_name("MODE")
+                .help("How cached results are validated: metadata (size, mtime, ctime, inode, device) or paranoid (also a partial content hash)")
+                .default_value("metadata")
+                .value_parser(value_parser!(CacheValidation)),
+        )
+        .arg(
+            Arg::new("import_state")
+                .long("import-state")
+                .value_name("PATH")
+                .value_hint(ValueHint::FilePath)
+                .help("Merge another state file (JSON, binary or SQLite) into the state before scanning")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("export_state")
+                .long("export-state")
+                .value_name("PATH")
+                .value_hint(ValueHint::FilePath)
+                .help("Write the state as JSON after scanning")
                 .value_parser(value_parser!(PathBuf)),
         )
         .arg(
@@ -63,7 +322,7 @@ fn main() {
             Arg::new("checkpoint")
                 .long("checkpoint")
                 .value_name("N")
-                .help("Save the resume JSON every N scanned files")
+                .help("Save the resume state every N scanned files")
                 .default_value("250")
                 .value_parser(value_parser!(usize)),
         )
@@ -75,8 +334,60 @@ fn main() {
                 .help("Set number of threads used for parallel scanning (default: Rayon default)")
                 .value_parser(value_parser!(usize)),
         )
+        .arg(
+            Arg::new("events")
+                .long("events")
+                .value_name("FORMAT")
+                .help("Stream scan events to stdout (ndjson); replaces the progress bars when piped")
+                .value_parser(["ndjson"]),
+        )
+        .arg(
+            Arg::new("log")
+                .long("log")
+                .value_name("PATH")
+                .value_hint(ValueHint::FilePath)
+                .help("Duplicate groups log (default: identical_files.log)")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("error_log")
+                .long("error-log")
+                .value_name("PATH")
+                .value_hint(ValueHint::FilePath)
+                .help("Processing errors log, created on the first error (default: identical_files_errors.log)")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("truncate_logs")
+                .long("truncate-logs")
+                .action(ArgAction::SetTrue)
+                .help("Start both logs fresh instead of appending to previous runs"),
+        )
+        .args(group_output_args())
         .get_matches();
 
+    match matches.subcommand() {
+        Some(("relink-playlists", sub)) => {
+            relink_playlists_command(sub);
+            return;
+        }
+        Some(("cache", sub)) => {
+            cache_command(sub);
+            return;
+        }
+        Some(("report", sub)) => {
+            report_command(sub);
+            return;
+        }
+        Some(("watch", sub)) => {
+            watch_command(sub);
+            return;
+        }
+        _ => {}
+    }
+
+    let run_info = run_info_from_matches(&matches);
+
     let threads = matches.get_one::<usize>("threads").copied();
     if let Some(threads) = threads {
         if threads == 0 {
@@ -92,13 +403,14 @@ fn main() {
             });
     }
 
-    let inputs: Vec<PathBuf> = matches
+    let mut inputs: Vec<PathBuf> = matches
         .get_many::<PathBuf>("input")
         .unwrap()
         .cloned()
         .collect();
     let list_files = !matches.get_flag("nolist");
     let skip_unique_size = matches.get_flag("skip_unique_size");
+    let find_overlaps = matches.get_flag("overlaps");
     let ignore_symlinks = matches.get_flag("nosym");
     let no_resume = matches.get_flag("no_resume");
     let ignore_size_expr = matches.get_one::<String>("ignore_size").cloned();
@@ -117,14 +429,112 @@ fn main() {
         eprintln!("--checkpoint must be at least 1");
         std::process::exit(2);
     }
+    let group_output = GroupOutput::from_matches(&matches);
+    // Reference directories are scanned too, unless an input already covers them
+    let canonical_inputs: Vec<PathBuf> = inputs
+        .iter()
+        .map(|input| std::fs::canonicalize(input).unwrap_or_else(|_| input.clone()))
+        .collect();
+    for dir in &group_output.reference_dirs {
+        if !canonical_inputs.iter().any(|input| dir.starts_with(input)) {
+            inputs.push(dir.clone());
+        }
+    }
+    group_output.check_roots(
+        &inputs
+            .iter()
+            .map(|input| std::fs::canonicalize(input).unwrap_or_else(|_| input.clone()))
+            .collect::<Vec<_>>(),
+    );
+    let events_enabled = matches.get_one::<String>("events").is_some();
+    let truncate_logs = matches.get_flag("truncate_logs");
+    let dupes_log = RunLog::new(
+        matches
+            .get_one::<PathBuf>("log")
+            .cloned()
+            .unwrap_or_else(|| PathBuf::from("identical_files.log")),
+        truncate_logs,
+        &run_info,
+    );
+    let error_log = Arc::new(RunLog::new(
+        matches
+            .get_one::<PathBuf>("error_log")
+            .cloned()
+            .unwrap_or_else(|| PathBuf::from("identical_files_errors.log")),
+        truncate_logs,
+        &run_info,
+    ));
+    // The error log is only created when an error happens, so a fresh run must drop the stale one
+    if truncate_logs {
+        let _ = std::fs::remove_file(error_log.path());
+    }
     let provided_state_file = matches.get_one::<PathBuf>("state_file").cloned();
     let resume_enabled = !no_resume;
-    let state_file = provided_state_file.unwrap_or_else(|| PathBuf::from("fadupes_state.json"));
+    // Before the binary format the default state was JSON; pick it up once if it is still around
+    let legacy_state_file = PathBuf::from("fadupes_state.json");
+    let migrate_legacy = provided_state_file.is_none()
+        && resume_enabled
+        && !Path::new("fadupes_state.bin").exists()
+        && legacy_state_file.exists();
+    let state_file = provided_state_file.unwrap_or_else(|| PathBuf::from("fadupes_state.bin"));
+    let state_format = matches
+        .get_one::<StateFormat>("state_format")
+        .copied()
+        .unwrap_or_else(|| StateFormat::from_path(&state_file));
+    let import_state = matches.get_one::<PathBuf>("import_state").cloned();
+    let export_state = matches.get_one::<PathBuf>("export_state").cloned();
+    if !resume_enabled && (import_state.is_some() || export_state.is_some()) {
+        eprintln!("--import-state and --export-state need the state file (drop --no-resume)");
+        std::process::exit(2);
+    }
     let resume_cache = if resume_enabled {
-        Some(Arc::new(ResumeCache::load(state_file, checkpoint)))
+        let mut cache = ResumeCache::load(
+            state_file,
+            state_format,
+            checkpoint,
+            matches.get_flag("wait_lock"),
+        )
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to open state file: {e}");
+            std::process::exit(1);
+        });
+        cache.validation = *matches
+            .get_one::<CacheValidation>("cache_validate")
+            .expect("defaulted above");
+        cache.need_lossless_check = group_output.keep_policy == KeepPolicy::HighestQuality;
+        Some(Arc::new(cache))
     } else {
         None
     };
+    if let Some(cache) = resume_cache.as_ref()
+        && migrate_legacy
+    {
+        match cache.import_state(&legacy_state_file) {
+            Ok(count) => eprintln!(
+                "Migrated {count} entries from {} to {} (the old file can be deleted)",
+                legacy_state_file.display(),
+                cache.path().display()
+            ),
+            Err(err) => eprintln!(
+                "Warning: cannot migrate {}: {err}",
+                legacy_state_file.display()
+            ),
+        }
+    }
+    if let (Some(cache), Some(path)) = (resume_cache.as_ref(), import_state.as_ref()) {
+        match cache.import_state(path) {
+            Ok(count) => eprintln!("Imported {count} state entries from {}", path.display()),
+            Err(err) => {
+                eprintln!("Failed to import state from {}: {err}", path.display());
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(cache) = resume_cache.as_ref()
+        && let Err(err) = cache.begin_scan(&run_info)
+    {
+        eprintln!("Warning: cannot record scan history: {err}");
+    }
 
     // If resume is enabled, trap Ctrl+C so we can persist the cache before exiting (130 = SIGINT)
     if let Some(cache) = resume_cache.as_ref() {
@@ -143,8 +553,27 @@ fn main() {
     // Create a HashSet of scanned directories to pass to the walk_dir function
     let scanned_dirs: HashSet<PathBuf> = inputs.iter().cloned().collect();
 
+    let events = if events_enabled {
+        EventSink::ndjson(std::io::stdout())
+    } else {
+        EventSink::disabled()
+    };
+    let scan_options = ScanOptions {
+        scanned_dirs,
+        list_files,
+        skip_unique_size,
+        ignore_symlinks,
+        resume_cache: resume_cache.clone(),
+        ignore_size,
+        // Events replace the progress UI when they are piped somewhere
+        show_progress: !events_enabled || std::io::stdout().is_terminal(),
+        events: events.clone(),
+        errors: Default::default(),
+        error_log: Arc::clone(&error_log),
+    };
+
     // Collect all the audio files from all inputs
-    let audio_files: Vec<AudioFile> = inputs
+    let mut audio_files: Vec<AudioFile> = inputs
         .into_par_iter() // Process directories in parallel
         .flat_map(|input| {
             let full_path = std::fs::canonicalize(&input).unwrap_or_else(|e| {
@@ -152,83 +581,911 @@ fn main() {
                 std::process::exit(1);
             });
 
-            AudioFile::walk_dir(
-                &full_path,
-                &scanned_dirs,
-                list_files,
-                skip_unique_size,
-                ignore_symlinks,
-                resume_cache.clone(),
-                ignore_size.as_ref(),
-            )
-            .into_par_iter()
+            AudioFile::walk_dir(&full_path, &scan_options).into_par_iter()
         })
         .collect();
 
-    compare_audio_files(&audio_files);
+    if let Some(cache) = resume_cache.as_ref() {
+        if let Err(err) = cache.finish_scan(audio_files.len()) {
+            eprintln!(
+                "Warning: failed to save state file {}: {err}",
+                cache.path().display()
+            );
+        }
+        if let Some(path) = export_state.as_ref() {
+            match cache.export_json(path) {
+                Ok(count) => eprintln!("Exported {count} state entries to {}", path.display()),
+                Err(err) => {
+                    eprintln!("Failed to export state to {}: {err}", path.display());
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    mark_reference(&mut audio_files, &group_output.reference_dirs);
+    let groups = compare_audio_files(&audio_files, &group_output, &dupes_log);
+    if find_overlaps {
+        eprintln!(
+            "Looking for tracks inside files longer than {} minutes...",
+            overlap::MIN_CONTAINER_SECS / 60.0
+        );
+    }
+    let overlaps = if find_overlaps {
+        overlap::find_overlaps(&audio_files, &|path, err| {
+            let error_message = format!("Error fingerprinting file: {path}: {err:?}");
+            eprintln!("{error_message}");
+            error_log.write_line(&error_message);
+            if let Ok(mut errors) = scan_options.errors.lock() {
+                errors.push(ScanError {
+                    path: path.to_string(),
+                    message: err.to_string(),
+                });
+            }
+        })
+    } else {
+        Vec::new()
+    };
+    let scan_errors = std::mem::take(&mut *scan_options.errors.lock().unwrap());
+    let mut report = Report::new(
+        run_info.clone(),
+        &audio_files,
+        &groups,
+        scan_errors,
+        group_output.keep_policy,
+    );
+    report.overlaps = overlaps;
+    for group in &report.groups {
+        events.emit(ScanEvent::GroupFound {
+            id: group.id,
+            reclaimable_bytes: group.reclaimable_bytes,
+            files: group.files.iter().map(|f| f.file_path.as_str()).collect(),
+        });
+    }
+    // With an event stream stdout is reserved for NDJSON, so the report only goes to --output
+    group_output.deliver(&report, &groups, !events_enabled);
+}
+
+// Parsed group_output_args
+struct GroupOutput {
+    keep_policy: KeepPolicy,
+    format: OutputFormat,
+    output_path: Option<PathBuf>,
+    emit_script: Option<PathBuf>,
+    script_action: DupeAction,
+    playlists_dir: Option<PathBuf>,
+    // Canonical --reference directories
+    reference_dirs: Vec<PathBuf>,
+    across_roots_only: bool,
+    match_options: MatchOptions,
+    interactive: bool,
+    plan_file: PathBuf,
+    journal_file: PathBuf,
+}
+
+impl GroupOutput {
+    // --across-roots-only keeps nothing with a single input, so say so instead of reporting no dupes
+    fn check_roots(&self, roots: &[PathBuf]) {
+        let distinct: HashSet<&PathBuf> = roots.iter().collect();
+        if self.across_roots_only && distinct.len() < 2 {
+            eprintln!("--across-roots-only needs at least two different -i inputs");
+            std::process::exit(2);
+        }
+    }
+
+    fn from_matches(matches: &ArgMatches) -> Self {
+        let format = if matches.get_one::<String>("compat").is_some() {
+            OutputFormat::Fdupes {
+                same_line: matches.get_flag("sameline"),
+                show_size: matches.get_flag("size"),
+            }
+        } else {
+            *matches
+                .get_one::<OutputFormat>("format")
+                .expect("defaulted above")
+        };
+        let script_action = DupeAction::parse(
+            matches
+                .get_one::<String>("script_action")
+                .expect("defaulted above"),
+            // The script may run from another directory, so pin the target down now
+            matches
+                .get_one::<PathBuf>("move_to")
+                .map(|dir| std::path::absolute(dir).unwrap_or_else(|_| dir.clone()))
+                .as_deref(),
+        )
+        .unwrap_or_else(|e| {
+            eprintln!("--script-action: {e} (see --move-to)");
+            std::process::exit(2);
+        });
+
+        GroupOutput {
+            keep_policy: *matches
+                .get_one::<KeepPolicy>("keep")
+                .expect("defaulted above"),
+            format,
+            output_path: matches.get_one::<PathBuf>("output").cloned(),
+            emit_script: matches.get_one::<PathBuf>("emit_script").cloned(),
+            script_action,
+            playlists_dir: matches.get_one::<PathBuf>("playlists").cloned(),
+            reference_dirs: matches
+                .get_many::<PathBuf>("reference")
+                .map(|dirs| {
+                    dirs.map(|dir| {
+                        std::fs::canonicalize(dir).unwrap_or_else(|e| {
+                            eprintln!("--reference {}: {e}", dir.display());
+                            std::process::exit(1);
+                        })
+                    })
+                    .collect()
+                })
+                .unwrap_or_default(),
+            across_roots_only: matches.get_flag("across_roots_only"),
+            match_options: MatchOptions {
+                channel_variants: matches.get_flag("channel_variants"),
+                gain_insensitive: matches.get_flag("gain_insensitive"),
+            },
+            interactive: matches.get_flag("interactive"),
+            plan_file: matches
+                .get_one::<PathBuf>("plan_file")
+                .cloned()
+                .unwrap_or_else(|| PathBuf::from("fadupes_plan.json")),
+            journal_file: matches
+                .get_one::<PathBuf>("journal")
+                .cloned()
+                .unwrap_or_else(|| PathBuf::from("fadupes_journal.jsonl")),
+        }
+    }
+
+    // Report, script, playlists and interactive review, in that order. Without --output the
+    // report goes to stdout only when `stdout_report` is set.
+    fn deliver(&self, report: &Report, groups: &[DuplicateGroup], stdout_report: bool) {
+        if stdout_report || self.output_path.is_some() {
+            write_report(report, self.format, self.output_path.as_deref());
+        }
+
+        if let Some(script_path) = self.emit_script.as_ref() {
+            let mut plan = ActionPlan::new(groups);
+            plan.apply_policy_to_unreviewed(self.keep_policy);
+            let written = std::fs::File::create(script_path).and_then(|file| {
+                let mut out = std::io::BufWriter::new(file);
+                write_script(&mut out, &plan, &self.script_action, &report.run)?;
+                out.flush()
+            });
+            match written {
+                Ok(()) => eprintln!("Shell script written to {}", script_path.display()),
+                Err(err) => {
+                    eprintln!("Failed to write script {}: {err}", script_path.display());
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        if let Some(dir) = self.playlists_dir.as_ref() {
+            match write_group_playlists(dir, groups) {
+                Ok(count) => eprintln!("Wrote {count} group playlists to {}", dir.display()),
+                Err(err) => {
+                    eprintln!("Failed to write playlists to {}: {err}", dir.display());
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        if self.interactive && !groups.is_empty() {
+            review_groups(
+                groups,
+                self.keep_policy,
+                &self.plan_file,
+                &self.journal_file,
+            );
+        }
+    }
+}
+
+// Shared by every command that opens the state file
+fn wait_lock_arg() -> Arg {
+    Arg::new("wait_lock")
+        .long("wait-lock")
+        .action(ArgAction::SetTrue)
+        .help("Wait for other fadupes runs using the state file instead of failing after 10s")
+}
+
+// Options deciding what happens with duplicate groups, shared by scans and `fadupes report`
+fn group_output_args() -> Vec<Arg> {
+    vec![
+        Arg::new("interactive")
+            .long("interactive")
+            .action(ArgAction::SetTrue)
+            .help("Review duplicate groups in an interactive terminal UI"),
+        Arg::new("keep")
+            .long("keep")
+            .value_name("POLICY")
+            .help("Keep policy: first, shortest-path, oldest, newest, largest, smallest, highest-quality")
+            .default_value("first")
+            .value_parser(value_parser!(KeepPolicy)),
+        Arg::new("reference")
+            .long("reference")
+            .value_name("DIR")
+            .action(ArgAction::Append)
+            .value_hint(ValueHint::DirPath)
+            .help("Trusted library (repeatable): only report groups with a file outside it, never act on its files")
+            .value_parser(value_parser!(PathBuf)),
+        Arg::new("across_roots_only")
+            .long("across-roots-only")
+            .action(ArgAction::SetTrue)
+            .help("Only report groups with files under at least two different inputs"),
+        Arg::new("channel_variants")
+            .long("channel-variants")
+            .action(ArgAction::SetTrue)
+            .help("Also group copies with swapped channels, inverted polarity, or dual mono vs mono"),
+        Arg::new("gain_insensitive")
+            .long("gain-insensitive")
+            .action(ArgAction::SetTrue)
+            .help("Also group copies that only differ by a constant gain, e.g. normalized or ReplayGain-applied (decodes candidates)"),
+        Arg::new("plan_file")
+            .long("plan-file")
+            .value_name("PATH")
+            .value_hint(ValueHint::FilePath)
+            .help("Where the interactive review exports its plan (default: fadupes_plan.json)")
+            .value_parser(value_parser!(PathBuf)),
+        Arg::new("journal")
+            .long("journal")
+            .value_name("PATH")
+            .value_hint(ValueHint::FilePath)
+            .help("Action journal appended when actions are executed (default: fadupes_journal.jsonl)")
+            .value_parser(value_parser!(PathBuf)),
+        Arg::new("emit_script")
+            .long("emit-script")
+            .value_name("PATH")
+            .value_hint(ValueHint::FilePath)
+            .help("Write a reviewable POSIX shell script acting on the duplicates instead of acting")
+            .value_parser(value_parser!(PathBuf)),
+        Arg::new("script_action")
+            .long("script-action")
+            .value_name("ACTION")
+            .help("What the emitted script does with duplicates: rm, ln, ln-s or mv")
+            .default_value("rm")
+            .value_parser(["rm", "ln", "ln-s", "mv"]),
+        Arg::new("move_to")
+            .long("move-to")
+            .value_name("DIR")
+            .value_hint(ValueHint::DirPath)
+            .help("Target directory for --script-action mv")
+            .value_parser(value_parser!(PathBuf)),
+        Arg::new("format")
+            .long("format")
+            .value_name("FORMAT")
+            .help("Output format for duplicate groups: text, json, csv, tsv, html or fdupes")
+            .default_value("text")
+            .value_parser(value_parser!(OutputFormat)),
+        Arg::new("compat")
+            .long("compat")
+            .value_name("TOOL")
+            .help("Render duplicate groups like fdupes/jdupes (both share the same layout)")
+            .conflicts_with("format")
+            .value_parser(["fdupes", "jdupes"]),
+        Arg::new("sameline")
+            .short('1')
+            .long("sameline")
+            .action(ArgAction::SetTrue)
+            .requires("compat")
+            .help("With --compat fdupes: list each group on a single line"),
+        Arg::new("size")
+            .short('S')
+            .long("size")
+            .action(ArgAction::SetTrue)
+            .requires("compat")
+            .help("With --compat fdupes: show the size of the duplicate files"),
+        Arg::new("output")
+            .short('o')
+            .long("output")
+            .value_name("PATH")
+            .value_hint(ValueHint::FilePath)
+            .help("Write the duplicate report to PATH instead of stdout")
+            .value_parser(value_parser!(PathBuf)),
+        Arg::new("playlists")
+            .long("playlists")
+            .value_name("DIR")
+            .value_hint(ValueHint::DirPath)
+            .help("Write one .m3u8 playlist per duplicate group into DIR")
+            .value_parser(value_parser!(PathBuf)),
+    ]
+}
+
+fn relink_playlists_command(matches: &ArgMatches) {
+    let roots: Vec<PathBuf> = matches
+        .get_many::<PathBuf>("input")
+        .expect("required")
+        .cloned()
+        .collect();
+    let journal_file = matches
+        .get_one::<PathBuf>("journal")
+        .cloned()
+        .unwrap_or_else(|| PathBuf::from("fadupes_journal.jsonl"));
+    let dry_run = matches.get_flag("dry_run");
+
+    let replaced = read_journal(&journal_file).unwrap_or_else(|e| {
+        eprintln!("Failed to read journal {}: {e}", journal_file.display());
+        std::process::exit(1);
+    });
+
+    let summary = relink_playlists(&roots, &replaced, dry_run);
+    for error in &summary.errors {
+        eprintln!("{error}");
+    }
+    println!(
+        "{} {} entries in {} of {} playlists",
+        if dry_run { "Would rewrite" } else { "Rewrote" },
+        summary.entries_rewritten,
+        summary.playlists_changed,
+        summary.playlists_scanned
+    );
+    if !summary.errors.is_empty() {
+        std::process::exit(1);
+    }
 }
 
-fn compare_audio_files(audio_files: &[AudioFile]) {
-    let log_file_path = "identical_files.log"; // path for the log file (current dir)
+// Initial scan of the inputs, then follow them with inotify until interrupted. Every batch of
+// changes updates the state file and prints the duplicate groups that involve changed files.
+#[cfg(target_os = "linux")]
+fn watch_command(matches: &ArgMatches) {
+    use fadupes::watch::{Change, DirWatcher};
+    use std::collections::BTreeSet;
+
+    let run_info = run_info_from_matches(matches);
+    let ignore_size: Option<SizeFilter> = matches
+        .get_one::<String>("ignore_size")
+        .map(|expr| parse_size_filter(expr))
+        .transpose()
+        .unwrap_or_else(|e| {
+            eprintln!("--ignore-size parse error: {e}");
+            std::process::exit(2);
+        });
+    let inputs: Vec<PathBuf> = matches
+        .get_many::<PathBuf>("input")
+        .unwrap()
+        .map(|input| {
+            std::fs::canonicalize(input).unwrap_or_else(|e| {
+                eprintln!("Error: {}: {e}", input.display());
+                std::process::exit(1);
+            })
+        })
+        .collect();
+    let events_enabled = matches.get_one::<String>("events").is_some();
+    let events = if events_enabled {
+        EventSink::ndjson(std::io::stdout())
+    } else {
+        EventSink::disabled()
+    };
+    let error_log = Arc::new(RunLog::new(
+        matches
+            .get_one::<PathBuf>("error_log")
+            .cloned()
+            .unwrap_or_else(|| PathBuf::from("identical_files_errors.log")),
+        false,
+        &run_info,
+    ));
+
+    let (_, cache) = open_state(matches, false);
+    let cache = Arc::new(cache);
+    let cache_for_signal = Arc::clone(&cache);
+    ctrlc::set_handler(move || {
+        let _ = cache_for_signal.save();
+        eprintln!(
+            "\nSaved resume state to {}",
+            cache_for_signal.path().display()
+        );
+        std::process::exit(130);
+    })
+    .expect("Error setting Ctrl+C handler");
 
-    // Open the log file in append mode (creates it if not exists), currently it's a simple txt file
-    let mut log_file = std::fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(log_file_path)
-        .expect("Unable to open log file");
+    let scan_options = ScanOptions {
+        scanned_dirs: inputs.iter().cloned().collect(),
+        list_files: false,
+        skip_unique_size: false,
+        ignore_symlinks: matches.get_flag("nosym"),
+        resume_cache: Some(Arc::clone(&cache)),
+        ignore_size,
+        show_progress: !events_enabled || std::io::stdout().is_terminal(),
+        events: events.clone(),
+        errors: Default::default(),
+        error_log,
+    };
+    let scan_all = || -> BTreeMap<String, AudioFile> {
+        inputs
+            .par_iter()
+            .flat_map(|input| AudioFile::walk_dir(input, &scan_options).into_par_iter())
+            .map(|file| (file.file_path.clone(), file))
+            .collect()
+    };
 
-    let mut file_map = HashMap::new();
-    let mut identical_groups = Vec::new();
+    // Watch before the initial scan so nothing written during it is missed
+    let mut watcher = DirWatcher::new().unwrap_or_else(|e| {
+        eprintln!("Failed to initialize inotify: {e}");
+        std::process::exit(1);
+    });
+    for input in &inputs {
+        if let Err(err) = watcher.add_tree(input) {
+            eprintln!("Failed to watch {}: {err}", input.display());
+            std::process::exit(1);
+        }
+    }
 
-    // Group files by their characteristics
-    for file in audio_files {
-        // Use bitwise float representation so grouping is exact
-        let key = (
-            file.total_samples,
-            file.sample_rate,
-            file.bit_depth,
-            file.channels,
-            file.peak_level.to_bits(),
-            file.rms_db_level.to_bits(),
+    if let Err(err) = cache.begin_scan(&run_info) {
+        eprintln!("Warning: cannot record scan history: {err}");
+    }
+    let mut library = scan_all();
+    if let Err(err) = cache.finish_scan(library.len()) {
+        eprintln!(
+            "Warning: failed to save state file {}: {err}",
+            cache.path().display()
         );
+    }
+    let files: Vec<AudioFile> = library.values().cloned().collect();
+    let groups = find_duplicate_groups(&files, &MatchOptions::default());
+    let scan_errors = std::mem::take(&mut *scan_options.errors.lock().unwrap());
+    let report = Report::new(
+        run_info.clone(),
+        &files,
+        &groups,
+        scan_errors,
+        KeepPolicy::First,
+    );
+    let mut next_group_id = 1;
+    emit_watch_groups(&report, &events, &mut next_group_id);
+    if !events_enabled {
+        write_report(&report, OutputFormat::Text, None);
+    }
+    eprintln!(
+        "Watching {} directories for new audio files (Ctrl+C to stop)",
+        watcher.watched_dirs()
+    );
+
+    loop {
+        let changes = watcher.next_changes().unwrap_or_else(|e| {
+            eprintln!("Watching failed: {e}");
+            std::process::exit(1);
+        });
+
+        let mut updated = BTreeSet::new();
+        let mut rescan = false;
+        for change in changes {
+            match change {
+                Change::Updated(path) => {
+                    updated.insert(path);
+                }
+                Change::Removed(path) => {
+                    library.retain(|file_path, _| !Path::new(file_path).starts_with(&path));
+                }
+                Change::Rescan => rescan = true,
+            }
+        }
+
+        let mut touched = HashSet::new();
+        if rescan {
+            eprintln!("Missed file system events, rescanning the inputs");
+            let rescanned = scan_all();
+            touched.extend(
+                rescanned
+                    .keys()
+                    .filter(|path| !library.contains_key(*path))
+                    .cloned(),
+            );
+            library = rescanned;
+        }
+        let scanned: Vec<(String, Option<AudioFile>)> = updated
+            .into_par_iter()
+            .map(|path| {
+                let file = AudioFile::scan_path(&path, &scan_options);
+                (path.to_string_lossy().to_string(), file)
+            })
+            .collect();
+        for (path, file) in scanned {
+            match file {
+                Some(file) => {
+                    library.insert(path.clone(), file);
+                    touched.insert(path);
+                }
+                // Not audio, filtered out or undecodable (the error is printed and logged)
+                None => {
+                    library.remove(&path);
+                }
+            }
+        }
+        if let Err(err) = cache.save() {
+            eprintln!(
+                "Warning: failed to save state file {}: {err}",
+                cache.path().display()
+            );
+        }
+        if touched.is_empty() {
+            continue;
+        }
+
+        let files: Vec<AudioFile> = library.values().cloned().collect();
+        let groups: Vec<DuplicateGroup> = find_duplicate_groups(&files, &MatchOptions::default())
+            .into_iter()
+            .filter(|group| group.files.iter().any(|f| touched.contains(&f.file_path)))
+            .collect();
+        let scan_errors = std::mem::take(&mut *scan_options.errors.lock().unwrap());
+        if groups.is_empty() {
+            continue;
+        }
+        let report = Report::new(
+            run_info.clone(),
+            &files,
+            &groups,
+            scan_errors,
+            KeepPolicy::First,
+        );
+        emit_watch_groups(&report, &events, &mut next_group_id);
+        if !events_enabled {
+            for group in &report.groups {
+                println!("New duplicates ({} files):", group.files.len());
+                for file in &group.files {
+                    println!("{}", file.file_path);
+                }
+                println!();
+            }
+        }
+    }
+}
+
+// Group ids keep counting across batches so consumers of the event stream can tell groups apart
+#[cfg(target_os = "linux")]
+fn emit_watch_groups(report: &Report, events: &EventSink, next_group_id: &mut usize) {
+    for group in &report.groups {
+        events.emit(ScanEvent::GroupFound {
+            id: *next_group_id,
+            reclaimable_bytes: group.reclaimable_bytes,
+            files: group.files.iter().map(|f| f.file_path.as_str()).collect(),
+        });
+        *next_group_id += 1;
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn watch_command(_matches: &ArgMatches) {
+    eprintln!("fadupes watch relies on inotify and is only available on Linux");
+    std::process::exit(1);
+}
+
+// State file of the subcommands working on previous scans (--state-file / --state-format)
+fn open_state(matches: &ArgMatches, must_exist: bool) -> (PathBuf, ResumeCache) {
+    let state_file = matches
+        .get_one::<PathBuf>("state_file")
+        .cloned()
+        .unwrap_or_else(|| {
+            // Fall back to the pre-binary default when it was never migrated
+            let legacy = PathBuf::from("fadupes_state.json");
+            if !Path::new("fadupes_state.bin").exists() && legacy.exists() {
+                legacy
+            } else {
+                PathBuf::from("fadupes_state.bin")
+            }
+        });
+    let state_format = matches
+        .get_one::<StateFormat>("state_format")
+        .copied()
+        .unwrap_or_else(|| StateFormat::from_path(&state_file));
+    if must_exist && !state_file.exists() {
+        eprintln!("State file {} not found", state_file.display());
+        std::process::exit(1);
+    }
+    let cache = ResumeCache::load(
+        state_file.clone(),
+        state_format,
+        usize::MAX,
+        matches.get_flag("wait_lock"),
+    )
+    .unwrap_or_else(|e| {
+        eprintln!("Failed to open state file {}: {e}", state_file.display());
+        std::process::exit(1);
+    });
+    (state_file, cache)
+}
 
-        file_map.entry(key).or_insert_with(Vec::new).push(file);
+fn cache_command(matches: &ArgMatches) {
+    let (name, sub) = matches.subcommand().expect("subcommand required");
+    // Only an import may create the state file
+    let (state_file, cache) = open_state(matches, name != "import");
+
+    match name {
+        "stats" => {
+            let stats = fadupes::cache::stats(&cache.entries());
+            let on_disk = std::fs::metadata(&state_file).map(|m| m.len()).unwrap_or(0);
+            println!(
+                "State file:       {} ({})",
+                state_file.display(),
+                format_size_bytes(on_disk)
+            );
+            println!("Entries:          {}", stats.entries);
+            for (format, count) in &stats.by_format {
+                println!("  {format:<15} {count}");
+            }
+            println!("Audio size:       {}", format_size_bytes(stats.audio_bytes));
+            println!("Audio duration:   {:.1} h", stats.duration_secs / 3600.0);
+            println!("With tags:        {}", stats.with_tags);
+            println!("With content key: {}", stats.with_content_key);
+            println!("Missing on disk:  {}", stats.missing);
+        }
+        "prune" => {
+            let dry_run = sub.get_flag("dry_run");
+            let missing = fadupes::cache::missing_paths(&cache.entries());
+            for path in &missing {
+                println!("{path}");
+            }
+            if !dry_run && let Err(err) = cache.remove_entries(&missing) {
+                eprintln!("Failed to save state file {}: {err}", state_file.display());
+                std::process::exit(1);
+            }
+            eprintln!(
+                "{} {} entries for missing files",
+                if dry_run { "Would remove" } else { "Removed" },
+                missing.len()
+            );
+        }
+        "list" => {
+            let prefix = sub
+                .get_one::<String>("prefix")
+                .map(String::as_str)
+                .unwrap_or("");
+            for entry in cache.entries() {
+                let file = &entry.audio_file;
+                if !file.file_path.starts_with(prefix) {
+                    continue;
+                }
+                println!(
+                    "{}\t{} {}Hz {}bit {}ch {:.1}s\t{}\t{}",
+                    file.file_path,
+                    file.format(),
+                    file.sample_rate,
+                    file.bit_depth,
+                    file.channels,
+                    file.duration_secs(),
+                    format_size_bytes(entry.file_size),
+                    fadupes::format_unix_time(entry.modified_secs)
+                );
+            }
+        }
+        "export" => {
+            let entries = cache.entries();
+            let csv = sub.get_one::<String>("format").map(String::as_str) == Some("csv");
+            let output = sub.get_one::<PathBuf>("output");
+            let mut out: Box<dyn Write> = match output {
+                Some(path) => Box::new(std::io::BufWriter::new(
+                    std::fs::File::create(path).unwrap_or_else(|e| {
+                        eprintln!("Failed to create {}: {e}", path.display());
+                        std::process::exit(1);
+                    }),
+                )),
+                None => Box::new(std::io::stdout().lock()),
+            };
+            let written = if csv {
+                fadupes::cache::write_csv(&mut out, &entries)
+            } else {
+                fadupes::cache::write_json(&mut out, &entries)
+            };
+            if let Err(err) = written.and_then(|()| out.flush()) {
+                eprintln!("Failed to export state: {err}");
+                std::process::exit(1);
+            }
+            if let Some(path) = output {
+                eprintln!("Exported {} entries to {}", entries.len(), path.display());
+            }
+        }
+        "import" => {
+            let file = sub.get_one::<PathBuf>("file").expect("required");
+            match cache.import_state(file) {
+                Ok(count) => eprintln!("Merged {count} entries from {}", file.display()),
+                Err(err) => {
+                    eprintln!("Failed to import {}: {err}", file.display());
+                    std::process::exit(1);
+                }
+            }
+        }
+        "verify" => {
+            let sample = *sub.get_one::<usize>("sample").expect("defaulted above");
+            let results = fadupes::cache::verify(&cache.entries(), sample);
+            let mut bad = 0;
+            for (path, outcome) in &results {
+                match outcome {
+                    VerifyOutcome::Ok => {}
+                    VerifyOutcome::Missing => println!("missing: {path}"),
+                    VerifyOutcome::Stale => println!("stale: {path}"),
+                    VerifyOutcome::Mismatch(what) => {
+                        bad += 1;
+                        println!("MISMATCH: {path}: {what}");
+                    }
+                    VerifyOutcome::Error(err) => {
+                        bad += 1;
+                        println!("error: {path}: {err}");
+                    }
+                }
+            }
+            eprintln!(
+                "Verified {} entries: {} ok, {} mismatched or unreadable",
+                results.len(),
+                results
+                    .iter()
+                    .filter(|(_, o)| matches!(o, VerifyOutcome::Ok))
+                    .count(),
+                bad
+            );
+            if bad > 0 {
+                std::process::exit(1);
+            }
+        }
+        _ => unreachable!("clap only accepts the subcommands above"),
     }
+}
 
-    // Collect identical files into groups
-    for (_, files) in &file_map {
-        if files.len() > 1 {
-            identical_groups.push(files);
+// Group the analysis stored in the state file without walking the library again
+fn report_command(matches: &ArgMatches) {
+    let run_info = run_info_from_matches(matches);
+    let group_output = GroupOutput::from_matches(matches);
+    let (_, cache) = open_state(matches, true);
+
+    let mut entries = cache.entries();
+    let roots: Vec<PathBuf> = matches
+        .get_many::<PathBuf>("input")
+        .map(|values| {
+            values
+                .map(|root| std::fs::canonicalize(root).unwrap_or_else(|_| root.clone()))
+                .collect()
+        })
+        .unwrap_or_default();
+    group_output.check_roots(&roots);
+    if !roots.is_empty() {
+        entries.retain(|entry| {
+            let path = Path::new(&entry.audio_file.file_path);
+            roots.iter().any(|root| path.starts_with(root))
+        });
+    }
+    if matches.get_flag("check_stale") {
+        let before = entries.len();
+        entries = entries
+            .into_par_iter()
+            .filter(|entry| fadupes::cache::disk_state(entry) == DiskState::Current)
+            .collect();
+        let dropped = before - entries.len();
+        if dropped > 0 {
+            eprintln!(
+                "Skipped {dropped} entries whose file is missing or changed since it was scanned"
+            );
         }
     }
 
-    // Output the results and write to the log file
-    if identical_groups.is_empty() {
-        println!("Among {} files, no dupes were found.", audio_files.len());
-    } else {
-        let total_dupes: usize = identical_groups.iter().map(|g| g.len()).sum();
-        println!("Found {} identical files:", total_dupes);
+    let mut audio_files: Vec<AudioFile> = entries.into_iter().map(|e| e.audio_file).collect();
+    // The state has no input roots, so credit each file to the deepest -i it is under
+    for audio_file in &mut audio_files {
+        audio_file.root = roots
+            .iter()
+            .filter(|root| Path::new(&audio_file.file_path).starts_with(root))
+            .max_by_key(|root| root.components().count())
+            .map(|root| root.display().to_string())
+            .unwrap_or_default();
+    }
+    mark_reference(&mut audio_files, &group_output.reference_dirs);
+    let mut groups = find_duplicate_groups(&audio_files, &group_output.match_options);
+    if group_output.across_roots_only {
+        retain_across_roots(&mut groups);
+    }
+    let report = Report::new(
+        run_info,
+        &audio_files,
+        &groups,
+        Vec::new(),
+        group_output.keep_policy,
+    );
+    group_output.deliver(&report, &groups, true);
+}
 
-        writeln!(log_file, "Identical Files Found:").expect("Failed to write to log file");
-        // Avoid logging the same dupe-group more than once in a single run (stable signature = sorted paths)
-        let mut seen_groups: HashSet<Vec<String>> = HashSet::new();
+// Record every argument (defaults included) so outputs describe exactly how they were produced
+fn run_info_from_matches(matches: &ArgMatches) -> RunInfo {
+    let inputs = matches
+        .get_many::<PathBuf>("input")
+        .map(|values| values.map(|p| p.display().to_string()).collect())
+        .unwrap_or_default();
+    let mut options = BTreeMap::new();
+    for id in matches.ids() {
+        let id = id.as_str();
+        if id == "input" {
+            continue;
+        }
+        if let Some(values) = matches.get_raw(id) {
+            let values: Vec<String> = values.map(|v| v.to_string_lossy().to_string()).collect();
+            options.insert(id.replace('_', "-"), values.join(","));
+        }
+    }
+    RunInfo::new(inputs, options)
+}
 
-        for group in identical_groups {
-            // stable signature: sorted list of paths
-            let mut sig: Vec<String> = group.iter().map(|f| f.file_path.clone()).collect();
-            sig.sort_unstable();
+fn review_groups(
+    groups: &[DuplicateGroup],
+    keep_policy: KeepPolicy,
+    plan_file: &Path,
+    journal_file: &Path,
+) {
+    let outcome = review(groups, keep_policy, plan_file).unwrap_or_else(|e| {
+        eprintln!("Interactive review failed: {e}");
+        std::process::exit(1);
+    });
 
-            if !seen_groups.insert(sig) {
-                continue; // already logged this exact set of paths in THIS run
+    match outcome {
+        ReviewOutcome::Quit => {}
+        ReviewOutcome::Exported(path) => println!("Plan exported to {}", path.display()),
+        ReviewOutcome::Execute(plan) => match plan.execute(journal_file) {
+            Ok(summary) => {
+                for error in &summary.errors {
+                    eprintln!("{error}");
+                }
+                println!(
+                    "Deleted {} files, reclaimed {} (journal: {})",
+                    summary.deleted,
+                    format_size_bytes(summary.reclaimed_bytes),
+                    journal_file.display()
+                );
+            }
+            Err(err) => {
+                eprintln!(
+                    "Failed to write action journal {}: {err}",
+                    journal_file.display()
+                );
+                std::process::exit(1);
             }
+        },
+    }
+}
+
+fn compare_audio_files(
+    audio_files: &[AudioFile],
+    group_output: &GroupOutput,
+    log: &RunLog,
+) -> Vec<DuplicateGroup> {
+    let mut identical_groups = find_duplicate_groups(audio_files, &group_output.match_options);
+    if group_output.across_roots_only {
+        retain_across_roots(&mut identical_groups);
+    }
 
-            writeln!(log_file, "#").expect("Failed to write to log file"); // Add separator for each dupe group
-            for file in group {
-                println!("{}", file.file_path);
-                writeln!(log_file, "{}", file.file_path).expect("Failed to write to log file");
+    // Write to the log file, the console/--output rendering is done by the report
+    if identical_groups.is_empty() {
+        log.write_line(&format!(
+            "Among {} files, no dupes were found.",
+            audio_files.len()
+        ));
+    } else {
+        log.write_line("Identical Files Found:");
+        for group in &identical_groups {
+            log.write_line("#"); // Add separator for each dupe group
+            for file in &group.files {
+                log.write_line(&file.file_path);
             }
-            println!(); // Add an empty line between dupe groups
         }
     }
+
+    identical_groups
+}
+
+// Render the report to --output, or stdout when no output file is given
+fn write_report(report: &Report, format: OutputFormat, output: Option<&Path>) {
+    let result = match output {
+        Some(path) => std::fs::File::create(path).and_then(|file| {
+            let mut out = std::io::BufWriter::new(file);
+            report.write(&mut out, format)?;
+            out.flush()
+        }),
+        None => {
+            let mut out = std::io::stdout().lock();
+            report.write(&mut out, format)
+        }
+    };
+
+    if let Err(err) = result {
+        match output {
+            Some(path) => eprintln!("Failed to write report {}: {err}", path.display()),
+            None => eprintln!("Failed to write report: {err}"),
+        }
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,214 @@
+use crate::groups::group_key;
+use crate::report::delimited::Delimiter;
+use crate::{AudioFile, CachedEntry, EntryCheck, FileStamp, format_unix_time, store};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::path::Path;
+
+// Summary of a state file for `fadupes cache stats`
+#[derive(Debug, Default)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub audio_bytes: u64,
+    pub duration_secs: f64,
+    pub by_format: BTreeMap<String, usize>,
+    pub with_tags: usize,
+    pub with_content_key: usize,
+    // Entries whose file is no longer on disk (what `cache prune` would remove)
+    pub missing: usize,
+}
+
+pub fn stats(entries: &[CachedEntry]) -> CacheStats {
+    let mut stats = CacheStats {
+        entries: entries.len(),
+        missing: missing_paths(entries).len(),
+        ..Default::default()
+    };
+    for entry in entries {
+        let file = &entry.audio_file;
+        stats.audio_bytes += entry.file_size;
+        stats.duration_secs += file.duration_secs();
+        *stats.by_format.entry(file.format()).or_default() += 1;
+        stats.with_tags += usize::from(!file.tags.is_empty());
+        stats.with_content_key += usize::from(entry.content_key.is_some());
+    }
+    stats
+}
+
+pub fn missing_paths(entries: &[CachedEntry]) -> Vec<String> {
+    entries
+        .par_iter()
+        .filter(|entry| !Path::new(&entry.audio_file.file_path).exists())
+        .map(|entry| entry.audio_file.file_path.clone())
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiskState {
+    Current,
+    Missing,
+    // Size, mtime or identity differ from the scan, so the cached analysis no longer applies
+    Changed,
+}
+
+// Same rules as a scan in the default validation mode
+pub fn disk_state(entry: &CachedEntry) -> DiskState {
+    let path = Path::new(&entry.audio_file.file_path);
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return DiskState::Missing;
+    };
+    let stamp = FileStamp::from_metadata(&metadata);
+    match entry.check(&stamp) {
+        EntryCheck::Current => DiskState::Current,
+        EntryCheck::IdentityChanged
+            if entry.content_key.is_some()
+                && store::content_key(path, stamp.size).ok() == entry.content_key =>
+        {
+            DiskState::Current
+        }
+        EntryCheck::IdentityChanged | EntryCheck::Changed => DiskState::Changed,
+    }
+}
+
+#[derive(Debug)]
+pub enum VerifyOutcome {
+    Ok,
+    Missing,
+    // File changed since the scan, or the entry predates an analysis scans now need: the entry
+    // would be ignored anyway
+    Stale,
+    // File unchanged on disk but decodes to different metrics: the entry is wrong
+    Mismatch(String),
+    Error(String),
+}
+
+// Evenly spread sample over the (path sorted) entries, so repeated runs check the same files.
+// A sample of 0 checks everything.
+pub fn verify(entries: &[CachedEntry], sample: usize) -> Vec<(String, VerifyOutcome)> {
+    let picked: Vec<&CachedEntry> = if sample == 0 || sample >= entries.len() {
+        entries.iter().collect()
+    } else {
+        (0..sample)
+            .map(|i| &entries[i * entries.len() / sample])
+            .collect()
+    };
+
+    picked
+        .into_par_iter()
+        .map(|entry| (entry.audio_file.file_path.clone(), verify_entry(entry)))
+        .collect()
+}
+
+fn verify_entry(entry: &CachedEntry) -> VerifyOutcome {
+    match disk_state(entry) {
+        DiskState::Current => {}
+        DiskState::Missing => return VerifyOutcome::Missing,
+        DiskState::Changed => return VerifyOutcome::Stale,
+    }
+
+    let path = Path::new(&entry.audio_file.file_path);
+    let fresh = match AudioFile::process_audio_file(path) {
+        Ok(fresh) => fresh,
+        Err(err) => return VerifyOutcome::Error(err.to_string()),
+    };
+    let cached = &entry.audio_file;
+    // Measured at the nominal bit depth before effective quality was recorded; scans decode
+    // such entries again anyway
+    if cached.effective_bit_depth == 0 && cached.is_hi_res() {
+        return VerifyOutcome::Stale;
+    }
+    let mut differences = Vec::new();
+    if group_key(&fresh) != group_key(cached) {
+        differences.push(format!(
+            "metrics {}Hz/{}bit/{}ch/{} samples/peak {}/rms {} vs cached {}Hz/{}bit/{}ch/{} samples/peak {}/rms {}",
+            fresh.sample_rate,
+            fresh.bit_depth,
+            fresh.channels,
+            fresh.total_samples,
+            fresh.peak_level,
+            fresh.rms_db_level,
+            cached.sample_rate,
+            cached.bit_depth,
+            cached.channels,
+            cached.total_samples,
+            cached.peak_level,
+            cached.rms_db_level
+        ));
+    }
+    if fresh.tags != cached.tags {
+        let keys: BTreeSet<&String> = fresh
+            .tags
+            .keys()
+            .chain(cached.tags.keys())
+            .filter(|key| fresh.tags.get(*key) != cached.tags.get(*key))
+            .collect();
+        let keys: Vec<&str> = keys.into_iter().map(String::as_str).collect();
+        differences.push(format!("tags differ: {}", keys.join(", ")));
+    }
+
+    if differences.is_empty() {
+        VerifyOutcome::Ok
+    } else {
+        VerifyOutcome::Mismatch(differences.join("; "))
+    }
+}
+
+const CSV_HEADER: [&str; 12] = [
+    "path",
+    "format",
+    "sample_rate",
+    "bit_depth",
+    "channels",
+    "duration_secs",
+    "peak_level",
+    "rms_db_level",
+    "file_size",
+    "modified_utc",
+    "modified_secs",
+    "content_key",
+];
+
+// One row per entry, same quoting rules as the CSV report
+pub fn write_csv<W: Write>(out: &mut W, entries: &[CachedEntry]) -> std::io::Result<()> {
+    let csv = Delimiter::Comma;
+    write!(
+        out,
+        "{}{}",
+        CSV_HEADER.join(csv.separator()),
+        csv.line_end()
+    )?;
+    for entry in entries {
+        let file = &entry.audio_file;
+        let fields = [
+            file.file_path.clone(),
+            file.format(),
+            file.sample_rate.to_string(),
+            file.bit_depth.to_string(),
+            file.channels.to_string(),
+            format!("{:.3}", file.duration_secs()),
+            file.peak_level.to_string(),
+            file.rms_db_level.to_string(),
+            entry.file_size.to_string(),
+            format_unix_time(entry.modified_secs),
+            entry.modified_secs.to_string(),
+            entry
+                .content_key
+                .map(|key| format!("{key:016x}"))
+                .unwrap_or_default(),
+        ];
+        let row: Vec<String> = fields.iter().map(|f| csv.escape(f)).collect();
+        write!(out, "{}{}", row.join(csv.separator()), csv.line_end())?;
+    }
+    Ok(())
+}
+
+// Same layout as the JSON state file, so the export can be imported again
+pub fn write_json<W: Write>(out: &mut W, entries: &[CachedEntry]) -> std::io::Result<()> {
+    let map: BTreeMap<&str, &CachedEntry> = entries
+        .iter()
+        .map(|entry| (entry.audio_file.file_path.as_str(), entry))
+        .collect();
+    serde_json::to_writer_pretty(&mut *out, &map)?;
+    writeln!(out)
+}
@@ -0,0 +1,84 @@
+use crate::AudioFile;
+use crate::actions::now_secs;
+use serde::Serialize;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+// One line of the --events ndjson stream
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum ScanEvent<'a> {
+    FileDiscovered {
+        path: &'a str,
+        file_size: u64,
+        modified_secs: u64,
+    },
+    SkippedUniqueSize {
+        path: &'a str,
+        file_size: u64,
+    },
+    CacheHit {
+        path: &'a str,
+    },
+    Processed {
+        file: &'a AudioFile,
+        elapsed_ms: u64,
+    },
+    Error {
+        path: &'a str,
+        message: &'a str,
+    },
+    GroupFound {
+        id: usize,
+        reclaimable_bytes: u64,
+        files: Vec<&'a str>,
+    },
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    ts: u64,
+    #[serde(flatten)]
+    event: ScanEvent<'a>,
+}
+
+// Cheap to clone, shared by every scanning thread. A disabled sink drops events without serializing.
+#[derive(Clone, Default)]
+pub struct EventSink {
+    out: Option<Arc<Mutex<Box<dyn Write + Send>>>>,
+}
+
+impl EventSink {
+    pub fn disabled() -> Self {
+        EventSink { out: None }
+    }
+
+    pub fn ndjson<W: Write + Send + 'static>(writer: W) -> Self {
+        EventSink {
+            out: Some(Arc::new(Mutex::new(Box::new(writer)))),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.out.is_some()
+    }
+
+    pub fn emit(&self, event: ScanEvent) {
+        let Some(out) = self.out.as_ref() else {
+            return;
+        };
+        let Ok(mut line) = serde_json::to_vec(&Envelope {
+            ts: now_secs(),
+            event,
+        }) else {
+            return;
+        };
+        line.push(b'\n');
+
+        // Whole lines under the lock so concurrent threads never interleave; flush for live consumers
+        if let Ok(mut out) = out.lock() {
+            let _ = out.write_all(&line);
+            let _ = out.flush();
+        }
+    }
+}
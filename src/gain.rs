@@ -0,0 +1,199 @@
+use crate::{AudioFile, ProcessError, SampleSink, StreamInfo, decode_samples};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::path::Path;
+
+// A fit this close leaves only requantization noise: what a normalized or ReplayGain-applied
+// copy looks like. Different recordings of the same length stay within a few dB of 0.
+pub const MAX_RESIDUAL_DB: f64 = -50.0;
+// Pre-filter on the scanned metrics: a constant gain moves every channel's RMS by the same
+// amount, and the peak too unless it clipped
+const RMS_TOLERANCE_DB: f64 = 0.2;
+const PEAK_TOLERANCE_DB: f64 = 0.5;
+const CLIPPED_PEAK: f32 = 0.99;
+
+// Least-squares gain taking `a` to `b`, over every sample of both files
+#[derive(Debug, Clone, Copy)]
+pub struct GainFit {
+    pub gain_db: f64,
+    // Energy left after removing the gain, relative to the energy of `b`
+    pub residual_db: f64,
+}
+
+impl GainFit {
+    pub fn between(a: &Path, b: &Path) -> Result<Option<GainFit>, ProcessError> {
+        let (_, fit) = decode_samples(a, FitAgainst { other: b })?;
+        fit
+    }
+}
+
+// Decodes `other` while the first file is being decoded, so neither is held in memory
+struct FitAgainst<'p> {
+    other: &'p Path,
+}
+
+impl SampleSink for FitAgainst<'_> {
+    type Output = Result<Option<GainFit>, ProcessError>;
+
+    fn consume<I: Iterator<Item = i32>>(self, info: &StreamInfo, samples: I) -> Self::Output {
+        let sink = Sums {
+            first: samples,
+            first_scale: full_scale(info.bit_depth),
+        };
+        let (_, fit) = decode_samples(self.other, sink)?;
+        Ok(fit)
+    }
+}
+
+struct Sums<I> {
+    first: I,
+    first_scale: f64,
+}
+
+impl<I: Iterator<Item = i32>> SampleSink for Sums<I> {
+    type Output = Option<GainFit>;
+
+    fn consume<J: Iterator<Item = i32>>(self, info: &StreamInfo, samples: J) -> Option<GainFit> {
+        let second_scale = full_scale(info.bit_depth);
+        let (mut aa, mut ab, mut bb) = (0f64, 0f64, 0f64);
+        for (a, b) in self.first.zip(samples) {
+            let a = a as f64 / self.first_scale;
+            let b = b as f64 / second_scale;
+            aa += a * a;
+            ab += a * b;
+            bb += b * b;
+        }
+        // Silence fits anything, and a negative gain is a polarity flip, not a level change
+        if aa == 0.0 || bb == 0.0 || ab <= 0.0 {
+            return None;
+        }
+        let gain = ab / aa;
+        let residual = (bb - gain * ab).max(0.0) / bb;
+        Some(GainFit {
+            gain_db: 20.0 * gain.log10(),
+            residual_db: if residual > 0.0 {
+                10.0 * residual.log10()
+            } else {
+                f64::NEG_INFINITY
+            },
+        })
+    }
+}
+
+fn full_scale(bit_depth: u32) -> f64 {
+    (1u64 << bit_depth.clamp(1, 32).saturating_sub(1)) as f64
+}
+
+fn amplitude_db(level: f32) -> f64 {
+    20.0 * f64::from(level).log10()
+}
+
+// Whether the scanned metrics allow `b` to be `a` at another level
+fn plausible(a: &AudioFile, b: &AudioFile) -> bool {
+    let offset = b.rms_db_level - a.rms_db_level;
+    let channels_agree = a.channel_stats.len() == b.channel_stats.len()
+        && a.channel_stats
+            .iter()
+            .zip(&b.channel_stats)
+            .all(|(ca, cb)| (cb.rms_db_level - ca.rms_db_level - offset).abs() <= RMS_TOLERANCE_DB);
+    let peaks_agree = a.peak_level >= CLIPPED_PEAK
+        || b.peak_level >= CLIPPED_PEAK
+        || (amplitude_db(b.peak_level) - amplitude_db(a.peak_level) - offset).abs()
+            <= PEAK_TOLERANCE_DB;
+    channels_agree && peaks_agree && a.peak_level > 0.0 && b.peak_level > 0.0
+}
+
+// Merge buckets of exact duplicates whose audio only differs by a constant gain. Buckets are
+// compared through their first file, within sets of equal length, rate and channel count.
+// Returns the merged buckets and each merged file's level in dB relative to the first bucket
+// of its set (files never merged are left out).
+pub(crate) fn merge_gain_variants(
+    buckets: Vec<Vec<&AudioFile>>,
+) -> (Vec<Vec<&AudioFile>>, HashMap<String, f64>) {
+    let mut candidates: HashMap<(u64, u32, u32), Vec<usize>> = HashMap::new();
+    for (idx, bucket) in buckets.iter().enumerate() {
+        let file = bucket[0];
+        candidates
+            .entry((file.total_samples, file.sample_rate, file.channels))
+            .or_default()
+            .push(idx);
+    }
+    let pairs: Vec<(usize, usize)> = candidates
+        .values()
+        .flat_map(|set| {
+            set.iter()
+                .enumerate()
+                .flat_map(move |(i, &a)| set[i + 1..].iter().map(move |&b| (a, b)))
+        })
+        .filter(|&(a, b)| plausible(buckets[a][0], buckets[b][0]))
+        .collect();
+    if pairs.is_empty() {
+        return (buckets, HashMap::new());
+    }
+    eprintln!(
+        "Comparing {} candidate pairs for gain offsets...",
+        pairs.len()
+    );
+
+    let matches: Vec<(usize, usize, f64)> = pairs
+        .into_par_iter()
+        .filter_map(|(a, b)| {
+            let (file_a, file_b) = (buckets[a][0], buckets[b][0]);
+            match GainFit::between(Path::new(&file_a.file_path), Path::new(&file_b.file_path)) {
+                Ok(Some(fit)) if fit.residual_db <= MAX_RESIDUAL_DB => Some((a, b, fit.gain_db)),
+                Ok(_) => None,
+                Err(err) => {
+                    eprintln!(
+                        "Gain check skipped {} / {}: {err}",
+                        file_a.file_path, file_b.file_path
+                    );
+                    None
+                }
+            }
+        })
+        .collect();
+
+    // Walk the matches from each unvisited bucket, adding up the offsets along the way
+    let mut edges: HashMap<usize, Vec<(usize, f64)>> = HashMap::new();
+    for &(a, b, gain_db) in &matches {
+        edges.entry(a).or_default().push((b, gain_db));
+        edges.entry(b).or_default().push((a, -gain_db));
+    }
+    let mut level: HashMap<usize, (usize, f64)> = HashMap::new();
+    let mut roots: Vec<usize> = edges.keys().copied().collect();
+    roots.sort_unstable();
+    for root in roots {
+        if level.contains_key(&root) {
+            continue;
+        }
+        level.insert(root, (root, 0.0));
+        let mut stack = vec![root];
+        while let Some(idx) = stack.pop() {
+            let (_, here) = level[&idx];
+            for &(next, gain_db) in &edges[&idx] {
+                if let Entry::Vacant(slot) = level.entry(next) {
+                    slot.insert((root, here + gain_db));
+                    stack.push(next);
+                }
+            }
+        }
+    }
+
+    let mut offsets = HashMap::new();
+    let mut merged: HashMap<usize, Vec<&AudioFile>> = HashMap::new();
+    let mut result = Vec::new();
+    for (idx, bucket) in buckets.into_iter().enumerate() {
+        match level.get(&idx) {
+            Some(&(root, offset)) => {
+                for file in &bucket {
+                    offsets.insert(file.file_path.clone(), offset);
+                }
+                merged.entry(root).or_default().extend(bucket);
+            }
+            None => result.push(bucket),
+        }
+    }
+    result.extend(merged.into_values());
+    (result, offsets)
+}
@@ -0,0 +1,417 @@
+use crate::{AudioFile, ChannelStats};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+// Exact match key: (total_samples, sample_rate, bit_depth, channels, peak bits, rms bits), on
+// the effective bit depth so a padded container still matches its source
+pub type GroupKey = (u64, u32, u32, u32, u32, u64);
+
+#[derive(Debug, Clone)]
+pub struct DuplicateGroup {
+    pub key: GroupKey,
+    pub files: Vec<AudioFile>,
+    // Level of each file in dB against a common reference, when --gain-insensitive merged copies
+    // at different levels into the group; empty otherwise
+    pub gain_offsets: BTreeMap<String, f64>,
+}
+
+impl DuplicateGroup {
+    pub fn total_bytes(&self) -> u64 {
+        self.files.iter().map(|f| f.file_size).sum()
+    }
+}
+
+pub fn group_key(file: &AudioFile) -> GroupKey {
+    // Use bitwise float representation so grouping is exact
+    (
+        file.total_samples,
+        file.sample_rate,
+        file.content_bit_depth(),
+        file.channels,
+        file.peak_level.to_bits(),
+        file.rms_db_level.to_bits(),
+    )
+}
+
+// Which differences between copies still count as a duplicate
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MatchOptions {
+    // Swapped channels, inverted polarity and dual-mono/mono pairs (--channel-variants)
+    pub channel_variants: bool,
+    // Copies that only differ by a constant gain (--gain-insensitive); decodes the candidates
+    pub gain_insensitive: bool,
+}
+
+// What files are grouped on: the exact key plus the signed channel hashes in channel order, or
+// with --channel-variants the set of sign-free channel hashes (one entry for a dual-mono file,
+// like its mono fold). Peak and RMS alone don't see channel order or polarity. Files scanned
+// before channel hashes were recorded keep their exact key without hashes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum MatchKey {
+    Exact(GroupKey, Vec<u64>),
+    Channels(u64, u32, Vec<u64>),
+}
+
+const CHANNEL_VARIANTS: MatchOptions = MatchOptions {
+    channel_variants: true,
+    gain_insensitive: false,
+};
+
+fn match_key(file: &AudioFile, options: &MatchOptions) -> MatchKey {
+    if !options.channel_variants || file.channel_stats.is_empty() {
+        let hashes = file.channel_stats.iter().map(|c| c.hash).collect();
+        return MatchKey::Exact(group_key(file), hashes);
+    }
+    let mut hashes: Vec<u64> = file.channel_stats.iter().map(|c| c.abs_hash).collect();
+    hashes.sort_unstable();
+    hashes.dedup();
+    MatchKey::Channels(file.total_samples, file.sample_rate, hashes)
+}
+
+// How `file` differs from `kept` when the two hold the same channels in another arrangement.
+// None for bit-identical channels, for files whose channels differ in content (such as gain
+// variants), or when either file has no channel hashes.
+pub fn channel_relation(file: &AudioFile, kept: &AudioFile) -> Option<String> {
+    let (ours, theirs) = (&file.channel_stats, &kept.channel_stats);
+    let channel_set = |file: &AudioFile| match match_key(file, &CHANNEL_VARIANTS) {
+        MatchKey::Channels(_, _, hashes) => Some(hashes),
+        MatchKey::Exact(..) => None,
+    };
+    let our_set = channel_set(file);
+    if our_set.is_none() || our_set != channel_set(kept) {
+        return None;
+    }
+    let signed = |stats: &[ChannelStats]| stats.iter().map(|c| c.hash).collect::<Vec<_>>();
+    let unsigned = |stats: &[ChannelStats]| stats.iter().map(|c| c.abs_hash).collect::<Vec<_>>();
+    let is_dual_mono = |stats: &[ChannelStats]| {
+        stats.len() > 1 && stats.iter().all(|c| c.abs_hash == stats[0].abs_hash)
+    };
+
+    if ours.len() != theirs.len() {
+        let inverted = if ours.iter().any(|o| theirs.iter().all(|t| t.hash != o.hash)) {
+            ", polarity inverted"
+        } else {
+            ""
+        };
+        return if is_dual_mono(ours) {
+            Some(format!("dual mono{inverted}"))
+        } else if is_dual_mono(theirs) {
+            Some(format!("mono fold{inverted}"))
+        } else {
+            Some("channel variant".to_string())
+        };
+    }
+
+    if signed(ours) == signed(theirs) {
+        return None;
+    }
+    let mut swapped_signed = signed(theirs);
+    swapped_signed.reverse();
+    let mut swapped_unsigned = unsigned(theirs);
+    swapped_unsigned.reverse();
+    let label = if ours.len() == 2 && signed(ours) == swapped_signed {
+        "channels swapped".to_string()
+    } else if unsigned(ours) == unsigned(theirs) {
+        let flipped: Vec<usize> = (0..ours.len())
+            .filter(|&ch| ours[ch].hash != theirs[ch].hash)
+            .collect();
+        if flipped.len() == ours.len() {
+            "polarity inverted".to_string()
+        } else if ours.len() == 2 {
+            let side = if flipped == [0] { "left" } else { "right" };
+            format!("{side} channel inverted")
+        } else {
+            let channels: Vec<String> = flipped.iter().map(|ch| (ch + 1).to_string()).collect();
+            format!("channel {} inverted", channels.join(", "))
+        }
+    } else if ours.len() == 2 && unsigned(ours) == swapped_unsigned {
+        "channels swapped, polarity inverted".to_string()
+    } else {
+        // Same channels, in an order and polarity no simple label covers
+        "channel variant".to_string()
+    };
+    Some(label)
+}
+
+// Group files by their characteristics, keeping only groups with more than one distinct path.
+// Groups made only of reference files are left out: the trusted library's own dupes don't matter.
+// Groups are returned in a stable order (by their first path) so output is reproducible across runs.
+pub fn find_duplicate_groups(
+    audio_files: &[AudioFile],
+    options: &MatchOptions,
+) -> Vec<DuplicateGroup> {
+    let mut file_map: HashMap<MatchKey, Vec<&AudioFile>> = HashMap::new();
+    for file in audio_files {
+        file_map
+            .entry(match_key(file, options))
+            .or_default()
+            .push(file);
+    }
+
+    let mut buckets: Vec<Vec<&AudioFile>> = file_map.into_values().collect();
+    let mut gain_offsets = HashMap::new();
+    if options.gain_insensitive {
+        (buckets, gain_offsets) = crate::gain::merge_gain_variants(buckets);
+    }
+
+    let mut groups: Vec<DuplicateGroup> = buckets
+        .into_iter()
+        .filter_map(|files| {
+            // The same path can show up twice when inputs overlap; it is not a dupe of itself
+            let mut seen_paths = HashSet::new();
+            let mut files: Vec<AudioFile> = files
+                .into_iter()
+                .filter(|f| seen_paths.insert(f.file_path.as_str()))
+                .cloned()
+                .collect();
+            if files.len() < 2 || files.iter().all(|f| f.reference) {
+                return None;
+            }
+            files.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+            let offsets: BTreeMap<String, f64> = files
+                .iter()
+                .filter_map(|f| Some((f.file_path.clone(), *gain_offsets.get(&f.file_path)?)))
+                .collect();
+            // Only worth showing when the merge brought in another level
+            let (low, high) = offsets
+                .values()
+                .fold((f64::MAX, f64::MIN), |(low, high), &o| {
+                    (low.min(o), high.max(o))
+                });
+            let levels_differ = high - low >= 0.005;
+            Some(DuplicateGroup {
+                key: group_key(&files[0]),
+                files,
+                gain_offsets: if levels_differ {
+                    offsets
+                } else {
+                    BTreeMap::new()
+                },
+            })
+        })
+        .collect();
+
+    groups.sort_by(|a, b| a.files[0].file_path.cmp(&b.files[0].file_path));
+    groups
+}
+
+// How the audio files of two directories relate, found by aggregating the file groups
+#[derive(Debug, Clone, Serialize)]
+pub struct AlbumMatch {
+    // `a` has every one of its tracks in `b`
+    pub a: String,
+    pub b: String,
+    pub a_tracks: usize,
+    pub b_tracks: usize,
+    // Tracks of `b` that have no copy in `a` (0 when both directories are identical)
+    pub missing: usize,
+}
+
+impl AlbumMatch {
+    pub fn identical(&self) -> bool {
+        self.missing == 0 && self.a_tracks == self.b_tracks
+    }
+
+    // "A/ duplicates B/ (12/12 tracks)", "A/ is missing 2 tracks of B/ (10/12 tracks)", or when
+    // either side holds copies of its own tracks "A/ has the same audio as B/ (12/13 tracks)"
+    pub fn describe(&self) -> String {
+        if self.identical() {
+            format!(
+                "{}/ duplicates {}/ ({}/{} tracks)",
+                self.a, self.b, self.a_tracks, self.b_tracks
+            )
+        } else if self.missing == 0 {
+            format!(
+                "{}/ has the same audio as {}/ ({}/{} tracks)",
+                self.a, self.b, self.a_tracks, self.b_tracks
+            )
+        } else {
+            format!(
+                "{}/ is missing {} track{} of {}/ ({}/{} tracks)",
+                self.a,
+                self.missing,
+                if self.missing == 1 { "" } else { "s" },
+                self.b,
+                self.a_tracks,
+                self.b_tracks
+            )
+        }
+    }
+}
+
+fn parent_dir(file: &AudioFile) -> &str {
+    Path::new(&file.file_path)
+        .parent()
+        .and_then(Path::to_str)
+        .unwrap_or("")
+}
+
+// Directories whose audio files all have a copy in another directory: identical albums, or an
+// album that is a subset of another. Track counts come from every scanned file, so a directory
+// with a track of its own is not mistaken for a copy. Single-track directories are left out,
+// those are just the file groups again.
+pub fn find_album_matches(audio_files: &[AudioFile], groups: &[DuplicateGroup]) -> Vec<AlbumMatch> {
+    let mut seen_paths = HashSet::new();
+    let mut tracks: HashMap<&str, usize> = HashMap::new();
+    for file in audio_files {
+        if seen_paths.insert(file.file_path.as_str()) {
+            *tracks.entry(parent_dir(file)).or_default() += 1;
+        }
+    }
+
+    // Per ordered pair of directories: the distinct files of the first with a copy in the second
+    let mut covered: BTreeMap<(&str, &str), HashSet<&str>> = BTreeMap::new();
+    for group in groups {
+        let mut per_dir: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for file in &group.files {
+            per_dir
+                .entry(parent_dir(file))
+                .or_default()
+                .push(&file.file_path);
+        }
+        for (dir, paths) in &per_dir {
+            for other in per_dir.keys().filter(|other| *other != dir) {
+                covered
+                    .entry((dir, other))
+                    .or_default()
+                    .extend(paths.iter().copied());
+            }
+        }
+    }
+
+    let mut matches = Vec::new();
+    for (&(a, b), a_paths) in &covered {
+        let (a_tracks, b_tracks) = (tracks[a], tracks[b]);
+        let (a_covered, b_covered) = (a_paths.len(), covered[&(b, a)].len());
+        if a_tracks < 2 || a_covered < a_tracks {
+            continue;
+        }
+        // Pairs covering each other are listed once, subsets from the smaller side
+        if b_covered == b_tracks && a > b {
+            continue;
+        }
+        matches.push(AlbumMatch {
+            a: a.to_string(),
+            b: b.to_string(),
+            a_tracks,
+            b_tracks,
+            missing: b_tracks - b_covered,
+        });
+    }
+    matches
+}
+
+// --across-roots-only: keep the groups with files under at least two input roots. Such a group
+// is kept whole, same-root copies included, so a keep policy still sees every copy.
+pub fn retain_across_roots(groups: &mut Vec<DuplicateGroup>) {
+    groups.retain(|group| {
+        let roots: HashSet<&str> = group.files.iter().map(|f| f.root.as_str()).collect();
+        roots.len() > 1
+    });
+}
+
+// Flag the files under any of the --reference directories (canonical paths, like the scan's)
+pub fn mark_reference(audio_files: &mut [AudioFile], reference_dirs: &[PathBuf]) {
+    for file in audio_files {
+        file.reference = reference_dirs
+            .iter()
+            .any(|dir| Path::new(&file.file_path).starts_with(dir));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A track whose audio is told apart by its peak level
+    fn track(path: &str, peak: f32) -> AudioFile {
+        AudioFile {
+            file_path: path.to_string(),
+            total_samples: 44_100,
+            sample_rate: 44_100,
+            bit_depth: 16,
+            channels: 2,
+            peak_level: peak,
+            ..Default::default()
+        }
+    }
+
+    fn albums(files: &[AudioFile]) -> Vec<String> {
+        let groups = find_duplicate_groups(files, &MatchOptions::default());
+        find_album_matches(files, &groups)
+            .iter()
+            .map(AlbumMatch::describe)
+            .collect()
+    }
+
+    fn with_channels(mut file: AudioFile, hashes: &[(u64, u64)]) -> AudioFile {
+        file.channel_stats = hashes
+            .iter()
+            .map(|&(hash, abs_hash)| ChannelStats {
+                peak_level: file.peak_level,
+                rms_db_level: file.rms_db_level,
+                hash,
+                abs_hash,
+            })
+            .collect();
+        file
+    }
+
+    #[test]
+    fn channel_variants_are_only_grouped_with_the_flag() {
+        let files = [
+            with_channels(track("/a/1.flac", 0.1), &[(1, 10), (2, 20)]),
+            with_channels(track("/a/1 inverted.flac", 0.1), &[(3, 10), (4, 20)]),
+            with_channels(track("/a/1 swapped.flac", 0.1), &[(2, 20), (1, 10)]),
+        ];
+        assert!(find_duplicate_groups(&files, &MatchOptions::default()).is_empty());
+        let variants = MatchOptions {
+            channel_variants: true,
+            ..Default::default()
+        };
+        let groups = find_duplicate_groups(&files, &variants);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].files.len(), 3);
+    }
+
+    #[test]
+    fn album_with_extra_copy_is_not_a_duplicate() {
+        let files = [
+            track("/a/1.flac", 0.1),
+            track("/a/2.flac", 0.2),
+            track("/b/1.flac", 0.1),
+            track("/b/1 copy.flac", 0.1),
+            track("/b/2.flac", 0.2),
+        ];
+        assert_eq!(
+            albums(&files),
+            ["/a/ has the same audio as /b/ (2/3 tracks)"]
+        );
+    }
+
+    #[test]
+    fn internal_dupe_counts_once_on_the_other_side() {
+        let files = [
+            track("/a/1.flac", 0.1),
+            track("/a/1 again.flac", 0.1),
+            track("/b/1.flac", 0.1),
+            track("/b/2.flac", 0.2),
+        ];
+        assert_eq!(
+            albums(&files),
+            ["/a/ is missing 1 track of /b/ (2/2 tracks)"]
+        );
+    }
+
+    #[test]
+    fn identical_albums_are_listed_once() {
+        let files = [
+            track("/a/1.flac", 0.1),
+            track("/a/2.flac", 0.2),
+            track("/b/1.flac", 0.1),
+            track("/b/2.flac", 0.2),
+        ];
+        assert_eq!(albums(&files), ["/a/ duplicates /b/ (2/2 tracks)"]);
+    }
+}
@@ -3,18 +3,39 @@ use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use rayon::prelude::*;
 use serde::de::Deserializer;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::ffi::OsString;
 use std::fs::File;
 use std::fs::read_link;
 use std::io::ErrorKind;
-use std::io::Write;
+use std::io::{Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use walkdir::WalkDir;
 
+use events::{EventSink, ScanEvent};
+use lossless::LosslessCheck;
+use run::{RunInfo, RunLog};
+use store::{BinaryStore, SqliteStore, StateBackend, StateFormat, StateLock};
+
+pub mod actions;
+pub mod cache;
+pub mod events;
+pub mod gain;
+pub mod groups;
+pub mod lossless;
+pub mod overlap;
+pub mod playlists;
+pub mod report;
+pub mod run;
+pub mod script;
+pub mod store;
+pub mod tui;
+#[cfg(target_os = "linux")]
+pub mod watch;
+
 #[derive(Clone, Debug)]
 pub enum SizeFilter {
     Lt(u64),
@@ -94,6 +115,104 @@ fn parse_size_bytes(s: &str) -> Result<u64, String> {
     Ok(bytes.round() as u64)
 }
 
+// Human readable size using the same binary units accepted by --ignore-size
+pub fn format_size_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+// UTC "YYYY-MM-DD HH:MM:SS" without pulling in a date crate (civil-from-days conversion)
+pub fn format_unix_time(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    let (hour, minute, second) = (rem / 3600, (rem % 3600) / 60, rem % 60);
+
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{year:04}-{month:02}-{day:02} {hour:02}:{minute:02}:{second:02}")
+}
+
+// Whole-second mtime as stored in the cache and the scan results
+pub fn file_modified_secs(metadata: &std::fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// Identity of a file beyond its size and whole-second mtime, so a rewrite within the same second
+// or a file swapped in under the same name is not served stale results
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileIdentity {
+    pub modified_nanos: u32,
+    pub changed_secs: i64,
+    pub changed_nanos: u32,
+    pub inode: u64,
+    pub device: u64,
+}
+
+// What a cache entry is validated against, read once per file during the walk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    pub size: u64,
+    pub modified_secs: u64,
+    pub identity: FileIdentity,
+}
+
+impl FileStamp {
+    pub fn from_metadata(metadata: &std::fs::Metadata) -> Self {
+        let modified_nanos = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        #[cfg(unix)]
+        let identity = {
+            use std::os::unix::fs::MetadataExt;
+            FileIdentity {
+                modified_nanos,
+                changed_secs: metadata.ctime(),
+                changed_nanos: metadata.ctime_nsec() as u32,
+                inode: metadata.ino(),
+                device: metadata.dev(),
+            }
+        };
+        // No stable ctime or file id in std elsewhere
+        #[cfg(not(unix))]
+        let identity = FileIdentity {
+            modified_nanos,
+            ..Default::default()
+        };
+
+        FileStamp {
+            size: metadata.len(),
+            modified_secs: file_modified_secs(metadata),
+            identity,
+        }
+    }
+}
+
 // Fallback RMS value used when data is missing or non-finite
 fn default_rms_db_level() -> f64 {
     -1000.0
@@ -121,6 +240,16 @@ fn clean_rms_db_level(v: f64) -> f64 {
     }
 }
 
+// Metrics of one channel. The hashes identify the exact sample sequence: `hash` as stored,
+// `abs_hash` with the sign dropped, so a polarity-inverted copy has the same `abs_hash` only.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ChannelStats {
+    pub peak_level: f32,
+    pub rms_db_level: f64,
+    pub hash: u64,
+    pub abs_hash: u64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AudioFile {
     pub file_path: String,
@@ -137,6 +266,27 @@ pub struct AudioFile {
     pub rms_db_level: f64,
     pub file_size: u64,
     pub modified_secs: u64,
+    // Vorbis comments (FLAC) or LIST/INFO chunk (WAV); keys are uppercased
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+    // One entry per channel; empty for entries written before they were recorded
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub channel_stats: Vec<ChannelStats>,
+    // Spectral cutoff and padded bits; None for entries written before they were recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lossless: Option<LosslessCheck>,
+    // Bit depth and sample rate the audio actually needs: below the nominal ones for padded or
+    // upsampled files. 0 for entries written before they were recorded.
+    #[serde(default)]
+    pub effective_bit_depth: u32,
+    #[serde(default)]
+    pub effective_sample_rate: u32,
+    // Under a --reference directory: grouped like any other file, but never acted on
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub reference: bool,
+    // Input root the file was found under during this run (empty for cached-only data)
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub root: String,
 }
 
 impl Default for AudioFile {
@@ -152,6 +302,13 @@ impl Default for AudioFile {
             rms_db_level: 0.0,
             file_size: 0,
             modified_secs: 0,
+            tags: BTreeMap::new(),
+            channel_stats: Vec::new(),
+            lossless: None,
+            effective_bit_depth: 0,
+            effective_sample_rate: 0,
+            reference: false,
+            root: String::new(),
         }
     }
 }
@@ -161,84 +318,283 @@ pub struct CachedEntry {
     pub audio_file: AudioFile,
     pub file_size: u64,
     pub modified_secs: u64,
+    // store::content_key of the file, used to find it again after a move or rename
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_key: Option<u64>,
+    // Missing in entries written by older versions; those are checked on size and mtime only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<FileIdentity>,
+}
+
+// How a cache entry relates to the file currently on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryCheck {
+    Current,
+    // Size and mtime (to the nanosecond) match but ctime, inode or device differ, as after a
+    // chmod, a restore or a remount. The bytes are probably unchanged; the content key can tell.
+    IdentityChanged,
+    Changed,
 }
 
+impl CachedEntry {
+    pub fn check(&self, stamp: &FileStamp) -> EntryCheck {
+        if self.file_size != stamp.size || self.modified_secs != stamp.modified_secs {
+            return EntryCheck::Changed;
+        }
+        match self.identity {
+            None => EntryCheck::Current,
+            Some(identity) if identity == stamp.identity => EntryCheck::Current,
+            Some(identity) if identity.modified_nanos != stamp.identity.modified_nanos => {
+                EntryCheck::Changed
+            }
+            Some(_) => EntryCheck::IdentityChanged,
+        }
+    }
+}
+
+// How much a cache hit must prove before its analysis is reused (--cache-validate)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CacheValidation {
+    // Size, mtime, ctime, inode and device
+    #[default]
+    Metadata,
+    // Also the content key (first and last 64 KiB) of every hit
+    Paranoid,
+}
+
+impl std::str::FromStr for CacheValidation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "metadata" => Ok(CacheValidation::Metadata),
+            "paranoid" => Ok(CacheValidation::Paranoid),
+            other => Err(format!(
+                "unknown cache validation \"{other}\" (use metadata or paranoid)"
+            )),
+        }
+    }
+}
+
+type ContentIndex = HashMap<(u64, u64), Vec<String>>;
+
 #[derive(Debug, Clone)]
 pub struct ResumeCache {
     pub path: PathBuf,
     pub data: Arc<Mutex<HashMap<String, CachedEntry>>>,
     pub save_every: usize,
+    pub validation: CacheValidation,
+    // Entries without a lossless check count as misses (--keep highest-quality ranks on it)
+    pub need_lossless_check: bool,
     pub pending: Arc<AtomicUsize>,
     save_lock: Arc<Mutex<()>>,
+    backend: Arc<StateBackend>,
+    // Paths stored or removed since the last save; the log/database only writes these
+    dirty: Arc<Mutex<HashSet<String>>>,
+    // (file size, content key) -> paths, to recognise files that moved since they were scanned.
+    // Several paths per key are normal here: they are the duplicates we are looking for.
+    by_content: Arc<Mutex<ContentIndex>>,
+    // Files decoded during this run, recorded in the scan history
+    processed: Arc<AtomicUsize>,
+    // Block on the state lock instead of giving up after a short wait
+    wait_lock: bool,
+    // store::disk_stamp after our last load or save; anything else means another process wrote
+    stamp: Arc<Mutex<Option<(u64, SystemTime)>>>,
 }
 
 impl ResumeCache {
-    pub fn load(path: PathBuf, save_every: usize) -> Self {
-        let data = match std::fs::File::open(&path) {
-            Ok(file) => match serde_json::from_reader::<_, HashMap<String, CachedEntry>>(file) {
-                Ok(map) => map,
+    pub fn load(
+        path: PathBuf,
+        format: StateFormat,
+        save_every: usize,
+        wait_lock: bool,
+    ) -> std::io::Result<Self> {
+        let _lock = StateLock::acquire(&path, wait_lock)?;
+        let (backend, data) = match format {
+            StateFormat::Binary => match BinaryStore::open(&path) {
+                Ok((store, map)) => (StateBackend::Binary(store), map),
+                // Written by a newer fadupes: starting over would throw its state away
+                Err(err) if err.kind() == ErrorKind::Unsupported => return Err(err),
                 Err(err) => {
                     eprintln!(
-                        "Warning: failed to parse state file {}: {err}. Starting with empty state.",
+                        "Warning: failed to load state file {}: {err}. Starting with empty state.",
                         path.display()
                     );
                     backup_broken(&path, &format!("{err}"));
-                    HashMap::new()
+                    (
+                        StateBackend::Binary(BinaryStore::open(&path)?.0),
+                        HashMap::new(),
+                    )
                 }
             },
-            Err(err) if err.kind() == ErrorKind::NotFound => HashMap::new(),
-            Err(err) => {
-                eprintln!(
-                    "Warning: failed to open state file {}: {err}. Starting with empty state.",
-                    path.display()
-                );
-                backup_broken(&path, &format!("{err}"));
-                HashMap::new()
+            StateFormat::Json => (StateBackend::Json, load_json_state(&path)),
+            StateFormat::Sqlite => {
+                let opened = SqliteStore::open(&path)
+                    .and_then(|store| store.load_all().map(|map| (store, map)));
+                let (store, map) = match opened {
+                    Ok(opened) => opened,
+                    // Written by a newer fadupes: starting over would throw its state away
+                    Err(err) if err.kind() == ErrorKind::Unsupported => return Err(err),
+                    Err(err) => {
+                        // Same recovery as a broken JSON file: keep the old one aside and start over
+                        eprintln!(
+                            "Warning: failed to open state database {}: {err}. Starting with empty state.",
+                            path.display()
+                        );
+                        backup_broken(&path, &format!("{err}"));
+                        (SqliteStore::open(&path)?, HashMap::new())
+                    }
+                };
+                (StateBackend::Sqlite(store), map)
             }
         };
 
-        ResumeCache {
+        let mut by_content = ContentIndex::new();
+        for (path, entry) in &data {
+            if let Some(key) = entry.content_key {
+                by_content
+                    .entry((entry.file_size, key))
+                    .or_default()
+                    .push(path.clone());
+            }
+        }
+
+        Ok(ResumeCache {
+            stamp: Arc::new(Mutex::new(store::disk_stamp(&path))),
             path,
             data: Arc::new(Mutex::new(data)),
             save_every,
+            validation: CacheValidation::default(),
+            need_lossless_check: false,
             pending: Arc::new(AtomicUsize::new(0)),
             save_lock: Arc::new(Mutex::new(())),
-        }
+            backend: Arc::new(backend),
+            dirty: Default::default(),
+            by_content: Arc::new(Mutex::new(by_content)),
+            processed: Arc::new(AtomicUsize::new(0)),
+            wait_lock,
+        })
     }
 
     pub fn path(&self) -> &Path {
         &self.path
     }
 
-    // Cache entry is valid only if size + modified time match (cheap change detector)
-    pub fn lookup(
-        &self,
-        file_path: &Path,
-        file_size: u64,
-        modified_secs: u64,
-    ) -> Option<AudioFile> {
+    // Whether an entry carries every analysis this run needs; older entries are decoded again
+    fn is_complete(&self, entry: &CachedEntry) -> bool {
+        let file = &entry.audio_file;
+        // Entries without channel hashes would not group with fresh ones, and hi-res entries
+        // without effective quality would group on their nominal bit depth
+        !file.channel_stats.is_empty()
+            && (!self.need_lossless_check || file.lossless.is_some())
+            && (file.effective_bit_depth != 0 || !file.is_hi_res())
+    }
+
+    // Cache entry is valid only if size, mtime, ctime, inode and device match (cheap change detector)
+    pub fn lookup(&self, file_path: &Path, stamp: &FileStamp) -> Option<AudioFile> {
         let map = self.data.lock().ok()?;
         map.get(&file_path.to_string_lossy().to_string())
-            .and_then(|entry| {
-                if entry.file_size == file_size && entry.modified_secs == modified_secs {
-                    Some(entry.audio_file.clone())
+            .filter(|entry| entry.check(stamp) == EntryCheck::Current && self.is_complete(entry))
+            .map(|entry| entry.audio_file.clone())
+    }
+
+    // Like lookup, but reads the file when metadata alone can't decide: entries whose ctime,
+    // inode or device changed and every hit in paranoid mode are confirmed by content key.
+    // A file missing from the cache under its path is also looked up by content, so moved or
+    // renamed files reuse their analysis and the entry is re-keyed to the new path. The content
+    // key only covers the ends of a file, so that takes a cached path that is gone and the same
+    // inode and device too: copies and look-alike files (stems, takes) are always decoded.
+    pub fn lookup_or_rekey(&self, file_path: &Path, stamp: &FileStamp) -> Option<AudioFile> {
+        let path = file_path.to_string_lossy().to_string();
+        let current = self
+            .data
+            .lock()
+            .ok()?
+            .get(&path)
+            .filter(|entry| self.is_complete(entry))
+            .cloned();
+        let mut key = None;
+
+        if let Some(entry) = current.as_ref() {
+            let check = entry.check(stamp);
+            let confirm = check == EntryCheck::IdentityChanged
+                || self.validation == CacheValidation::Paranoid;
+            if check != EntryCheck::Changed {
+                key = if confirm || entry.content_key.is_none() {
+                    store::content_key(file_path, stamp.size).ok()
                 } else {
-                    None
+                    entry.content_key
+                };
+                if !confirm || (key.is_some() && key == entry.content_key) {
+                    // Entries from older versions get their content key and identity on a hit
+                    if entry.content_key != key || entry.identity != Some(stamp.identity) {
+                        self.insert(CachedEntry {
+                            content_key: key.or(entry.content_key),
+                            identity: Some(stamp.identity),
+                            ..entry.clone()
+                        });
+                    }
+                    return Some(entry.audio_file.clone());
                 }
-            })
-    }
+            }
+        }
 
-    pub fn store(&self, audio_file: AudioFile, file_size: u64, modified_secs: u64) {
-        if let Ok(mut map) = self.data.lock() {
-            map.insert(
-                audio_file.file_path.clone(),
-                CachedEntry {
-                    audio_file,
-                    file_size,
-                    modified_secs,
-                },
-            );
+        let key = match key {
+            Some(key) => key,
+            None => store::content_key(file_path, stamp.size).ok()?,
+        };
+        // Without inode numbers (non-Unix) a move can't be told from a look-alike file
+        if stamp.identity.inode == 0 {
+            return None;
         }
+        let candidates: Vec<String> = self
+            .by_content
+            .lock()
+            .ok()?
+            .get(&(stamp.size, key))?
+            .iter()
+            .filter(|old_path| **old_path != path && !Path::new(old_path).exists())
+            .cloned()
+            .collect();
+        let (old_path, old_entry) = {
+            let map = self.data.lock().ok()?;
+            candidates.into_iter().find_map(|old_path| {
+                let entry = map.get(&old_path).filter(|e| {
+                    e.file_size == stamp.size
+                        && e.content_key == Some(key)
+                        && e.identity.is_some_and(|id| {
+                            id.inode == stamp.identity.inode && id.device == stamp.identity.device
+                        })
+                        && self.is_complete(e)
+                })?;
+                Some((old_path, entry.clone()))
+            })?
+        };
+
+        let mut audio_file = old_entry.audio_file;
+        audio_file.file_path = path;
+        audio_file.modified_secs = stamp.modified_secs;
+        self.remove(&old_path);
+        self.insert(CachedEntry {
+            audio_file: audio_file.clone(),
+            file_size: stamp.size,
+            modified_secs: stamp.modified_secs,
+            content_key: Some(key),
+            identity: Some(stamp.identity),
+        });
+        Some(audio_file)
+    }
+
+    pub fn store(&self, audio_file: AudioFile, stamp: &FileStamp) {
+        self.processed.fetch_add(1, Ordering::Relaxed);
+        let content_key = store::content_key(Path::new(&audio_file.file_path), stamp.size).ok();
+        self.insert(CachedEntry {
+            audio_file,
+            file_size: stamp.size,
+            modified_secs: stamp.modified_secs,
+            content_key,
+            identity: Some(stamp.identity),
+        });
 
         // Throttle disk writes: save cache every 'save_every' inserts (AtomicUsize so threads coordinate cheaply)
         let count = self.pending.fetch_add(1, Ordering::Relaxed) + 1;
@@ -249,28 +605,235 @@ impl ResumeCache {
         }
     }
 
+    fn insert(&self, entry: CachedEntry) {
+        let path = entry.audio_file.file_path.clone();
+        self.put(entry);
+        if let Ok(mut dirty) = self.dirty.lock() {
+            dirty.insert(path);
+        }
+    }
+
+    fn remove(&self, path: &str) {
+        self.take(path);
+        if let Ok(mut dirty) = self.dirty.lock() {
+            dirty.insert(path.to_string());
+        }
+    }
+
+    // insert/remove without marking the path for the next save
+    fn put(&self, entry: CachedEntry) {
+        let path = entry.audio_file.file_path.clone();
+        let key = entry.content_key.map(|k| (entry.file_size, k));
+        let previous = self
+            .data
+            .lock()
+            .ok()
+            .and_then(|mut map| map.insert(path.clone(), entry));
+        let previous_key = previous.and_then(|e| e.content_key.map(|k| (e.file_size, k)));
+        if previous_key != key {
+            self.unindex(&path, previous_key);
+            if let Some(key) = key
+                && let Ok(mut by_content) = self.by_content.lock()
+            {
+                by_content.entry(key).or_default().push(path);
+            }
+        }
+    }
+
+    fn take(&self, path: &str) {
+        let removed = self.data.lock().ok().and_then(|mut map| map.remove(path));
+        self.unindex(
+            path,
+            removed.and_then(|e| e.content_key.map(|k| (e.file_size, k))),
+        );
+    }
+
+    fn unindex(&self, path: &str, key: Option<(u64, u64)>) {
+        if let Some(key) = key
+            && let Ok(mut by_content) = self.by_content.lock()
+            && let Some(paths) = by_content.get_mut(&key)
+        {
+            paths.retain(|p| p != path);
+            if paths.is_empty() {
+                by_content.remove(&key);
+            }
+        }
+    }
+
     pub fn save(&self) -> std::io::Result<()> {
         // Serialize writers to the temp file/rename to avoid corruption from concurrent saves
         let _lock = self.save_lock.lock().unwrap();
+        if self.dirty.lock().unwrap().is_empty() {
+            return Ok(());
+        }
+        // Same for other processes sharing the state file
+        let _state_lock = StateLock::acquire(&self.path, self.wait_lock)?;
+        self.merge_from_disk()?;
+
+        let dirty = std::mem::take(&mut *self.dirty.lock().unwrap());
+
+        let saved = match self.backend.as_ref() {
+            StateBackend::Binary(log) => {
+                let (entries, removed) = self.changes_for(&dirty);
+                log.append(&entries, &removed).and_then(|()| {
+                    let snapshot = {
+                        let map = self.data.lock().unwrap();
+                        log.needs_compaction(map.len()).then(|| map.clone())
+                    };
+                    match snapshot {
+                        Some(snapshot) => log.compact(&snapshot),
+                        None => Ok(()),
+                    }
+                })
+            }
+            StateBackend::Json => {
+                let snapshot = {
+                    let map = self.data.lock().unwrap();
+                    map.clone()
+                };
+                store::write_json(&self.path, &snapshot)
+            }
+            StateBackend::Sqlite(db) => {
+                let (entries, removed) = self.changes_for(&dirty);
+                db.apply_changes(&entries, &removed)
+            }
+        };
+
+        // Keep the unsaved paths around so the next checkpoint retries them
+        match saved {
+            Ok(()) => *self.stamp.lock().unwrap() = store::disk_stamp(&self.path),
+            Err(_) => self.dirty.lock().unwrap().extend(dirty),
+        }
+        saved
+    }
+
+    // Take over what other processes saved since our last load or save, so that rewriting the
+    // JSON file or compacting the log doesn't drop their entries. Our unsaved changes win.
+    // The database is updated row by row and needs none of this.
+    fn merge_from_disk(&self) -> std::io::Result<()> {
+        let stamp = store::disk_stamp(&self.path);
+        if stamp.is_none() || stamp == *self.stamp.lock().unwrap() {
+            return Ok(());
+        }
+        let disk = match self.backend.as_ref() {
+            StateBackend::Binary(log) => log.reload()?,
+            StateBackend::Json => store::read_json(&self.path)?,
+            StateBackend::Sqlite(_) => return Ok(()),
+        };
+
+        // Held throughout so a concurrent store isn't overwritten by the older entry on disk
+        let dirty = self.dirty.lock().unwrap();
+        let gone: Vec<String> = self
+            .data
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|path| !dirty.contains(*path) && !disk.contains_key(*path))
+            .cloned()
+            .collect();
+        for path in gone {
+            self.take(&path);
+        }
+        for (path, entry) in disk {
+            if !dirty.contains(&path) {
+                self.put(entry);
+            }
+        }
+        Ok(())
+    }
+
+    // Split dirty paths into entries to write and paths that left the cache
+    fn changes_for(&self, paths: &HashSet<String>) -> (Vec<CachedEntry>, Vec<String>) {
+        let map = self.data.lock().unwrap();
+        let mut entries = Vec::new();
+        let mut removed = Vec::new();
+        for path in paths {
+            match map.get(path) {
+                Some(entry) => entries.push(entry.clone()),
+                None => removed.push(path.clone()),
+            }
+        }
+        (entries, removed)
+    }
 
+    // Record this run in the scan history (database state only)
+    pub fn begin_scan(&self, run: &RunInfo) -> std::io::Result<()> {
+        match self.backend.as_ref() {
+            StateBackend::Binary(_) | StateBackend::Json => Ok(()),
+            StateBackend::Sqlite(db) => db.begin_scan(run),
+        }
+    }
+
+    // Final save of the run, closing its scan history entry
+    pub fn finish_scan(&self, files_seen: usize) -> std::io::Result<()> {
+        self.save()?;
+        match self.backend.as_ref() {
+            StateBackend::Binary(_) | StateBackend::Json => Ok(()),
+            StateBackend::Sqlite(db) => {
+                db.finish_scan(files_seen, self.processed.load(Ordering::Relaxed))
+            }
+        }
+    }
+
+    // Merge another state file (any format) into this one. For paths known to both, the entry
+    // with the newer mtime wins. Returns the number of entries taken over.
+    pub fn import_state(&self, path: &Path) -> std::io::Result<usize> {
+        let imported = store::read_state(path)?;
+        let mut count = 0;
+        for entry in imported.into_values() {
+            let newer = self
+                .data
+                .lock()
+                .unwrap()
+                .get(&entry.audio_file.file_path)
+                .is_none_or(|current| entry.modified_secs >= current.modified_secs);
+            if newer {
+                self.insert(entry);
+                count += 1;
+            }
+        }
+        self.save()?;
+        Ok(count)
+    }
+
+    // Snapshot of every entry, sorted by path
+    pub fn entries(&self) -> Vec<CachedEntry> {
+        let mut entries: Vec<CachedEntry> = self.data.lock().unwrap().values().cloned().collect();
+        entries.sort_by(|a, b| a.audio_file.file_path.cmp(&b.audio_file.file_path));
+        entries
+    }
+
+    // Drop entries by path and persist the removal right away
+    pub fn remove_entries(&self, paths: &[String]) -> std::io::Result<()> {
+        for path in paths {
+            self.remove(path);
+        }
+        self.save()
+    }
+
+    // Write the whole state in the historical JSON layout; returns the entry count
+    pub fn export_json(&self, path: &Path) -> std::io::Result<usize> {
         let snapshot = {
             let map = self.data.lock().unwrap();
             map.clone()
         };
+        store::write_json(path, &snapshot)?;
+        Ok(snapshot.len())
+    }
+}
 
-        if let Some(parent) = self.path.parent() {
-            if !parent.as_os_str().is_empty() {
-                std::fs::create_dir_all(parent)?;
-            }
+fn load_json_state(path: &Path) -> HashMap<String, CachedEntry> {
+    match store::read_json(path) {
+        Ok(map) => map,
+        Err(err) if err.kind() == ErrorKind::NotFound => HashMap::new(),
+        Err(err) => {
+            eprintln!(
+                "Warning: failed to load state file {}: {err}. Starting with empty state.",
+                path.display()
+            );
+            backup_broken(path, &format!("{err}"));
+            HashMap::new()
         }
-
-        // Atomic-ish save: write to a temp file then rename, so we don't leave a half-written JSON behind
-        let tmp_path = self.path.with_extension("tmp");
-        let file = File::create(&tmp_path)?;
-        serde_json::to_writer(&file, &snapshot)?;
-        file.sync_all()?; // ensure bytes hit disk before rename
-        std::fs::rename(tmp_path, &self.path)?;
-        Ok(())
     }
 }
 
@@ -280,41 +843,165 @@ impl Drop for ResumeCache {
     }
 }
 
+// Everything walk_dir needs besides the root being walked
+#[derive(Clone)]
+pub struct ScanOptions {
+    pub scanned_dirs: HashSet<PathBuf>,
+    pub list_files: bool,
+    pub skip_unique_size: bool,
+    pub ignore_symlinks: bool,
+    pub resume_cache: Option<Arc<ResumeCache>>,
+    pub ignore_size: Option<SizeFilter>,
+    // Draw indicatif bars; disabled when stdout carries an event stream to a pipe
+    pub show_progress: bool,
+    pub events: EventSink,
+    // Files that failed to decode, collected across threads for the report
+    pub errors: Arc<Mutex<Vec<ScanError>>>,
+    // Lazily created on the first error (shared by every input root)
+    pub error_log: Arc<RunLog>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanError {
+    pub path: String,
+    pub message: String,
+}
+
 impl AudioFile {
+    // Container format derived from the extension ("FLAC", "WAV")
+    pub fn format(&self) -> String {
+        Path::new(&self.file_path)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_uppercase())
+            .unwrap_or_default()
+    }
+
+    pub fn duration_secs(&self) -> f64 {
+        if self.sample_rate == 0 {
+            0.0
+        } else {
+            self.total_samples as f64 / self.sample_rate as f64
+        }
+    }
+
+    // Effective bit depth, or the nominal one for entries scanned before it was recorded
+    pub fn content_bit_depth(&self) -> u32 {
+        if self.effective_bit_depth == 0 {
+            self.bit_depth
+        } else {
+            self.effective_bit_depth
+        }
+    }
+
+    // Effective sample rate, or the nominal one for entries scanned before it was recorded
+    pub fn content_sample_rate(&self) -> u32 {
+        if self.effective_sample_rate == 0 {
+            self.sample_rate
+        } else {
+            self.effective_sample_rate
+        }
+    }
+
+    // Whether the effective bit depth or sample rate can differ from the nominal ones
+    pub fn is_hi_res(&self) -> bool {
+        self.bit_depth > 16 || self.sample_rate > 48_000
+    }
+
+    // Short "Artist - Title" label for UIs, empty when the file carries no tags
+    pub fn tag_label(&self) -> String {
+        let artist = self.tags.get("ARTIST").map(String::as_str).unwrap_or("");
+        let title = self.tags.get("TITLE").map(String::as_str).unwrap_or("");
+        match (artist.is_empty(), title.is_empty()) {
+            (false, false) => format!("{artist} - {title}"),
+            (true, false) => title.to_string(),
+            (false, true) => artist.to_string(),
+            (true, true) => String::new(),
+        }
+    }
+
+    // --skip-unique-size: no other candidate has this byte size
+    fn is_unique_skip(
+        stamp: &FileStamp,
+        skip_unique_size: bool,
+        size_counts: Option<&HashMap<u64, usize>>,
+    ) -> bool {
+        skip_unique_size
+            && size_counts
+                .and_then(|map| map.get(&stamp.size))
+                .copied()
+                .unwrap_or(0)
+                <= 1
+    }
+
     // Shared helper: decide if an entry should be skipped (unique size) or served from cache.
     fn skip_or_cached(
         entry: &walkdir::DirEntry,
-        size: u64,
-        modified_secs: u64,
+        stamp: &FileStamp,
         skip_unique_size: bool,
         size_counts: Option<&HashMap<u64, usize>>,
         resume_cache: Option<&Arc<ResumeCache>>,
     ) -> (bool, Option<AudioFile>) {
-        let is_unique_skip = skip_unique_size
-            && size_counts
-                .and_then(|map| map.get(&size))
-                .copied()
-                .unwrap_or(0)
-                <= 1;
-
+        let is_unique_skip = Self::is_unique_skip(stamp, skip_unique_size, size_counts);
         let cached = resume_cache
-            .and_then(|cache| cache.lookup(entry.path(), size, modified_secs));
-
+            .filter(|_| !is_unique_skip)
+            .and_then(|cache| cache.lookup_or_rekey(entry.path(), stamp));
         (is_unique_skip, cached)
     }
 
+    // FLAC and WAV files up to 800MB, minus what --ignore-size excludes
+    fn is_candidate(path: &Path, size: u64, options: &ScanOptions) -> bool {
+        // Apply optional ignore filter from --ignore-size
+        if options
+            .ignore_size
+            .as_ref()
+            .is_some_and(|flt| flt.should_ignore(size))
+        {
+            return false;
+        }
+
+        let size_ok = size <= 800 * 1024 * 1024; // Check if file is <= 800MB
+
+        // Filter by file extension (flac or wav) and file size
+        let Some(extension) = path.extension() else {
+            return false;
+        };
+        (extension == "flac" || extension == "wav") && size_ok
+    }
+
+    // walk_dir for a single file, as `fadupes watch` sees them: same filters, cache and events.
+    // None for files that are not scanned or fail to decode.
+    pub fn scan_path(path: &Path, options: &ScanOptions) -> Option<AudioFile> {
+        let metadata = std::fs::metadata(path).ok()?;
+        if !metadata.is_file() || !Self::is_candidate(path, metadata.len(), options) {
+            return None;
+        }
+        let stamp = FileStamp::from_metadata(&metadata);
+        let path_str = path.to_string_lossy();
+        options.events.emit(ScanEvent::FileDiscovered {
+            path: &path_str,
+            file_size: stamp.size,
+            modified_secs: stamp.modified_secs,
+        });
+
+        if let Some(audio_file) = options
+            .resume_cache
+            .as_ref()
+            .and_then(|cache| cache.lookup_or_rekey(path, &stamp))
+        {
+            options.events.emit(ScanEvent::CacheHit { path: &path_str });
+            return Some(audio_file);
+        }
+        Self::process_and_store(path, &stamp, options)
+    }
+
     // Walk through the directory to find audio files (FLAC and WAV) in parallel with progress bar
-    pub fn walk_dir(
-        dir: &PathBuf,
-        scanned_dirs: &HashSet<PathBuf>,
-        list_files: bool,
-        skip_unique_size: bool,
-        ignore_symlinks: bool,
-        resume_cache: Option<Arc<ResumeCache>>,
-        ignore_size: Option<&SizeFilter>,
-    ) -> Vec<AudioFile> {
-        // Lazily open the error log only if we hit an error (shared across threads via Mutex<Option<File>>)
-        let error_log_file: Arc<Mutex<Option<File>>> = Arc::new(Mutex::new(None));
+    pub fn walk_dir(dir: &PathBuf, options: &ScanOptions) -> Vec<AudioFile> {
+        let events = &options.events;
+        let resume_cache = options.resume_cache.as_ref();
+        let skip_unique_size = options.skip_unique_size;
+        let ignore_symlinks = options.ignore_symlinks;
+        // The per-file list is part of the progress UI, so it goes away with it
+        let list_files = options.list_files && options.show_progress;
 
         // Collect the list of audio files to process
         // Build the full candidate list up front; we need it to compute unique-size skips
@@ -335,7 +1022,7 @@ impl AudioFile {
                     // Check if it's a symlink and resolve it
                     if let Ok(symlink_target) = read_link(path) {
                         // If symlink points to one of the directories being scanned, ignore it
-                        if scanned_dirs.contains(&symlink_target) {
+                        if options.scanned_dirs.contains(&symlink_target) {
                             eprintln!(
                                 "Skipping symlink pointing to a scanned dir: {}",
                                 path.display()
@@ -349,28 +1036,14 @@ impl AudioFile {
                     return None;
                 };
 
-                let size = metadata.len();
-                // Apply optional ignore filter from --ignore-size
-                if ignore_size.is_some_and(|flt| flt.should_ignore(size)) {
-                    return None;
-                }
-
-                let size_ok = metadata.len() <= 800 * 1024 * 1024; // Check if file is <= 800MB
-
-                // Filter by file extension (flac or wav) and file size
-                let Some(extension) = f.path().extension() else {
-                    return None;
-                };
-
-                if (extension == "flac" || extension == "wav") && size_ok {
-                    let size = metadata.len();
-                    let modified_secs = metadata
-                        .modified()
-                        .ok()
-                        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
-                        .map(|d| d.as_secs())
-                        .unwrap_or(0);
-                    Some((f, size, modified_secs))
+                if Self::is_candidate(f.path(), metadata.len(), options) {
+                    let stamp = FileStamp::from_metadata(&metadata);
+                    events.emit(ScanEvent::FileDiscovered {
+                        path: &f.path().to_string_lossy(),
+                        file_size: stamp.size,
+                        modified_secs: stamp.modified_secs,
+                    });
+                    Some((f, stamp))
                 } else {
                     None
                 }
@@ -380,34 +1053,28 @@ impl AudioFile {
         // Precompute size counts if we need to skip unique sizes
         let size_counts = if skip_unique_size {
             let mut counts = std::collections::HashMap::new();
-            for (_, size, _) in &files_to_process {
-                *counts.entry(*size).or_insert(0usize) += 1;
+            for (_, stamp) in &files_to_process {
+                *counts.entry(stamp.size).or_insert(0usize) += 1;
             }
             Some(counts)
         } else {
             None
         };
 
-        // Count how many entries are already satisfied (cached) or will be skipped (unique size)
+        // Unique-size skips are known upfront; cache hits are counted as the main pass finds them,
+        // since confirming one can take a read of the file
         let initial_processed = files_to_process
             .iter()
-            .filter(|(entry, size, modified_secs)| {
-                let (is_unique_skip, cached) = Self::skip_or_cached(
-                    entry,
-                    *size,
-                    *modified_secs,
-                    skip_unique_size,
-                    size_counts.as_ref(),
-                    resume_cache.as_ref(),
-                );
-
-                is_unique_skip || cached.is_some()
+            .filter(|(_, stamp)| {
+                Self::is_unique_skip(stamp, skip_unique_size, size_counts.as_ref())
             })
             .count();
 
         let total_files = files_to_process.len();
 
-        let (progress_bar, list_mp) = if list_files {
+        let (progress_bar, list_mp) = if !options.show_progress {
+            (ProgressBar::hidden(), None)
+        } else if list_files {
             let mp = Arc::new(MultiProgress::new());
             let total_pb = mp.add(ProgressBar::new(total_files as u64));
             total_pb.set_style(
@@ -431,7 +1098,7 @@ impl AudioFile {
         let audio_files: Vec<AudioFile> = if list_files {
             let start_counter = Arc::new(AtomicUsize::new(initial_processed));
             // Limiti UI noise, cap to <= 8 spinner lines and reuse them by assigning files round-robin to a "slot"
-            let max_bars = std::cmp::max(1, std::cmp::min(rayon::current_num_threads(), 8));
+            let max_bars = rayon::current_num_threads().clamp(1, 8);
             let list_bars: Arc<Vec<ProgressBar>> = Arc::new(
                 (0..max_bars)
                     .map(|_| {
@@ -451,21 +1118,23 @@ impl AudioFile {
             );
             files_to_process
                 .par_iter()
-                .filter_map(|(entry, size, modified_secs)| {
+                .filter_map(|(entry, stamp)| {
                     let path_str = entry.path().to_string_lossy().to_string();
                     let progress = progress_bar.clone();
 
                     let (is_unique_skip, cached) = Self::skip_or_cached(
                         entry,
-                        *size,
-                        *modified_secs,
+                        stamp,
                         skip_unique_size,
                         size_counts.as_ref(),
-                        resume_cache.as_ref(),
+                        resume_cache,
                     );
-                    let already_processed = is_unique_skip || cached.is_some();
 
                     if is_unique_skip {
+                        events.emit(ScanEvent::SkippedUniqueSize {
+                            path: &path_str,
+                            file_size: stamp.size,
+                        });
                         if let Some(ref mp) = list_mp {
                             let _ = mp.println(format!(
                                 "Skipping unique-size file: {}",
@@ -476,15 +1145,14 @@ impl AudioFile {
                     }
 
                     if let Some(audio_file) = cached {
+                        events.emit(ScanEvent::CacheHit { path: &path_str });
                         if let Some(ref mp) = list_mp {
                             let _ = mp.println(format!(
                                 "Using cached result for: {}",
                                 entry.path().display()
                             ));
                         }
-                        if !already_processed {
-                            progress.inc(1);
-                        }
+                        progress.inc(1);
                         return Some(audio_file);
                     }
 
@@ -497,40 +1165,9 @@ impl AudioFile {
                         Some(pb.clone())
                     };
 
-                    let result = match AudioFile::process_audio_file(entry) {
-                        Ok(mut audio_file) => {
-                            audio_file.file_size = *size;
-                            audio_file.modified_secs = *modified_secs;
-                            if let Some(cache) = resume_cache.as_ref() {
-                                cache.store(audio_file.clone(), *size, *modified_secs);
-                            }
-                            Some(audio_file)
-                        }
-                        Err(err) => {
-                            let error_message =
-                                format!("Error processing file: {}: {:?}", path_str, err);
-                            println!("{}", error_message);
-                            let mut error_log = error_log_file.lock().unwrap();
-                            if error_log.is_none() {
-                                *error_log = Some(
-                                    std::fs::OpenOptions::new()
-                                        .create(true)
-                                        .append(true)
-                                        .open("identical_files_errors.log")
-                                        .expect("Unable to open error log file"),
-                                );
-                            }
-                            if let Some(file) = error_log.as_mut() {
-                                writeln!(file, "{}", error_message)
-                                    .expect("Failed to write to error log file");
-                            }
-                            None
-                        }
-                    };
+                    let result = Self::process_and_store(entry.path(), stamp, options);
 
-                    if !already_processed {
-                        progress.inc(1);
-                    }
+                    progress.inc(1);
 
                     if let Some(pb) = per_file_pb {
                         pb.set_message(String::new());
@@ -542,175 +1179,290 @@ impl AudioFile {
         } else {
             files_to_process
                 .par_iter()
-                .filter_map(|(entry, size, modified_secs)| {
+                .filter_map(|(entry, stamp)| {
                     let path_str = entry.path().to_string_lossy().to_string();
                     let progress = progress_bar.clone();
 
                     let (is_unique_skip, cached) = Self::skip_or_cached(
                         entry,
-                        *size,
-                        *modified_secs,
+                        stamp,
                         skip_unique_size,
                         size_counts.as_ref(),
-                        resume_cache.as_ref(),
+                        resume_cache,
                     );
-                    let already_processed = is_unique_skip || cached.is_some();
 
                     if is_unique_skip {
+                        events.emit(ScanEvent::SkippedUniqueSize {
+                            path: &path_str,
+                            file_size: stamp.size,
+                        });
                         return None;
                     }
 
                     if let Some(audio_file) = cached {
-                        if !already_processed {
-                            progress.inc(1);
-                        }
+                        events.emit(ScanEvent::CacheHit { path: &path_str });
+                        progress.inc(1);
                         return Some(audio_file);
                     }
 
-                    let result = match AudioFile::process_audio_file(entry) {
-                        Ok(mut audio_file) => {
-                            audio_file.file_size = *size;
-                            audio_file.modified_secs = *modified_secs;
-                            if let Some(cache) = resume_cache.as_ref() {
-                                cache.store(audio_file.clone(), *size, *modified_secs);
-                            }
-                            Some(audio_file)
-                        }
-                        Err(err) => {
-                            let error_message =
-                                format!("Error processing file: {}: {:?}", path_str, err);
-                            println!("{}", error_message);
-                            let mut error_log = error_log_file.lock().unwrap();
-                            if error_log.is_none() {
-                                *error_log = Some(
-                                    std::fs::OpenOptions::new()
-                                        .create(true)
-                                        .append(true)
-                                        .open("identical_files_errors.log")
-                                        .expect("Unable to open error log file"),
-                                );
-                            }
-                            if let Some(file) = error_log.as_mut() {
-                                writeln!(file, "{}", error_message)
-                                    .expect("Failed to write to error log file");
-                            }
-                            None
-                        }
-                    };
+                    let result = Self::process_and_store(entry.path(), stamp, options);
 
-                    if !already_processed {
-                        progress.inc(1);
-                    }
+                    progress.inc(1);
                     result
                 })
                 .collect()
         };
 
-        if let Some(cache) = resume_cache.as_ref() {
+        if let Some(cache) = resume_cache {
             let _ = cache.save();
         }
 
         progress_bar.finish_with_message("All files processed");
+        let root = dir.to_string_lossy();
         audio_files
+            .into_iter()
+            .map(|mut audio_file| {
+                audio_file.root = root.to_string();
+                audio_file
+            })
+            .collect()
     }
 
-    // Process individual audio files (FLAC and WAV)
-    pub fn process_audio_file(entry: &walkdir::DirEntry) -> Result<AudioFile, ProcessError> {
-        let extension = entry
-            .path()
-            .extension()
-            .and_then(|ext| ext.to_str())
-            .unwrap_or("");
-        let mut audio_file = AudioFile {
-            file_path: entry.path().to_string_lossy().to_string(), // Store the full path
-            ..Default::default()
-        };
-
-        match extension {
-            "flac" => {
-                let mut reader = Self::load_flac(entry.path())?;
-                let stream_info = reader.streaminfo();
-                let total_samples = stream_info.samples.ok_or(ProcessError::NoSamplesFound)?;
-                audio_file.total_samples = total_samples;
-                audio_file.sample_rate = stream_info.sample_rate;
-                audio_file.bit_depth = stream_info.bits_per_sample;
-                audio_file.channels = stream_info.channels;
-
-                let (peak_level, rms_db_level) = Self::accumulate_metrics(
-                    reader.samples().map(|sample| sample.unwrap_or(0)),
-                    stream_info.bits_per_sample as i32,
-                );
-                audio_file.peak_level = peak_level;
-                audio_file.rms_db_level = clean_rms_db_level(rms_db_level);
+    // Decode one file, store it in the cache and report the outcome (shared by both walk_dir branches)
+    fn process_and_store(
+        path: &Path,
+        stamp: &FileStamp,
+        options: &ScanOptions,
+    ) -> Option<AudioFile> {
+        let path_str = path.to_string_lossy().to_string();
+        let started = Instant::now();
+
+        match AudioFile::process_audio_file(path) {
+            Ok(mut audio_file) => {
+                audio_file.file_size = stamp.size;
+                audio_file.modified_secs = stamp.modified_secs;
+                options.events.emit(ScanEvent::Processed {
+                    file: &audio_file,
+                    elapsed_ms: started.elapsed().as_millis() as u64,
+                });
+                if let Some(cache) = options.resume_cache.as_ref() {
+                    cache.store(audio_file.clone(), stamp);
+                }
+                Some(audio_file)
             }
-            "wav" => {
-                let mut reader =
-                    WavReader::open(entry.path()).map_err(|_| ProcessError::NonFlacError)?;
-                let spec = reader.spec();
-                audio_file.total_samples = reader.duration() as u64;
-                audio_file.sample_rate = spec.sample_rate;
-                audio_file.bit_depth = spec.bits_per_sample as u32;
-                audio_file.channels = spec.channels as u32;
-
-                // Read with the correct sample width so 24/32-bit WAVs are handled correctly
-                let (peak_level, rms_db_level) = match spec.bits_per_sample {
-                    8 => Self::accumulate_metrics(
-                        reader.samples::<i8>().map(|s| s.unwrap_or(0) as i32),
-                        8,
-                    ),
-                    16 => Self::accumulate_metrics(
-                        reader.samples::<i16>().map(|s| s.unwrap_or(0) as i32),
-                        16,
-                    ),
-                    24 | 32 => Self::accumulate_metrics(
-                        reader.samples::<i32>().map(|s| s.unwrap_or(0)),
-                        spec.bits_per_sample as i32,
-                    ),
-                    _ => return Err(ProcessError::UnsupportedBitDepth),
-                };
-                audio_file.peak_level = peak_level;
-                audio_file.rms_db_level = clean_rms_db_level(rms_db_level);
+            Err(err) => {
+                let error_message = format!("Error processing file: {}: {:?}", path_str, err);
+                // stdout belongs to the event stream when one is requested
+                if options.events.is_enabled() {
+                    options.events.emit(ScanEvent::Error {
+                        path: &path_str,
+                        message: &err.to_string(),
+                    });
+                } else {
+                    println!("{}", error_message);
+                }
+                options.error_log.write_line(&error_message);
+                if let Ok(mut errors) = options.errors.lock() {
+                    errors.push(ScanError {
+                        path: path_str,
+                        message: err.to_string(),
+                    });
+                }
+                None
             }
-            _ => return Err(ProcessError::UnsupportedBitDepth),
         }
+    }
 
-        Ok(audio_file)
+    // Process individual audio files (FLAC and WAV)
+    pub fn process_audio_file(path: &Path) -> Result<AudioFile, ProcessError> {
+        let (info, (effective_bit_depth, (peak_level, rms_db_level, channel_stats), lossless)) =
+            decode_samples(path, Metrics)?;
+        Ok(AudioFile {
+            file_path: path.to_string_lossy().to_string(), // Store the full path
+            total_samples: info.total_samples,
+            sample_rate: info.sample_rate,
+            bit_depth: info.bit_depth,
+            channels: info.channels,
+            peak_level,
+            rms_db_level: clean_rms_db_level(rms_db_level),
+            tags: info.tags,
+            channel_stats,
+            lossless: Some(lossless),
+            effective_bit_depth,
+            effective_sample_rate: lossless.effective_sample_rate(info.sample_rate),
+            ..Default::default()
+        })
     }
 
-    // Single-pass over samples: compute peak + RMS(dB). Empty input => fallback dB to avoid log10(0)
-    fn accumulate_metrics<I>(samples: I, bit_depth: i32) -> (f32, f64)
+    // Single-pass over samples: compute peak + RMS(dB) of the whole file and of every channel,
+    // plus the per-channel hashes. Files of more than 16 bits are also measured as if they were
+    // 16/24-bit, in case their low bits turn out to be padding; one (bit depth, metrics) pair per
+    // view, the file's own bit depth first. Empty input => fallback dB to avoid log10(0)
+    fn accumulate_metrics<I>(
+        samples: I,
+        bit_depth: i32,
+        channels: usize,
+    ) -> Vec<(u32, LevelMetrics)>
     where
         I: Iterator<Item = i32>,
     {
-        let max_amplitude = Self::get_max_amplitude(bit_depth) as f64;
-        if max_amplitude <= 0.0 {
-            return (0.0, default_rms_db_level());
+        let Some(nominal) = MetricsView::new(bit_depth, 0, channels) else {
+            return vec![(bit_depth as u32, (0.0, default_rms_db_level(), Vec::new()))];
+        };
+        let mut views = vec![nominal];
+        views.extend(
+            [24, 16]
+                .into_iter()
+                .filter(|&depth| depth < bit_depth)
+                .filter_map(|depth| MetricsView::new(depth, (bit_depth - depth) as u32, channels)),
+        );
+        for sample in samples {
+            for view in &mut views {
+                view.push(sample);
+            }
+        }
+        views
+            .into_iter()
+            .map(|view| (view.bit_depth as u32, view.finish()))
+            .collect()
+    }
+
+    fn get_max_amplitude(bit_depth: i32) -> i32 {
+        match bit_depth {
+            8 => i8::MAX as i32,
+            16 => i16::MAX as i32,
+            24 => (1 << 23) - 1,
+            32 => i32::MAX,
+            _ => 0,
         }
+    }
 
-        let mut max_abs = 0i32;
-        let mut squared_sum = 0f64;
-        let mut count = 0u64;
+    fn load_flac(path: &Path) -> Result<claxon::FlacReader<File>, ProcessError> {
+        let flac_file = File::open(path)?;
+        let reader = claxon::FlacReader::new(flac_file)?;
+        Ok(reader)
+    }
+}
 
-        for sample in samples {
-            let abs = sample.abs() as i32;
-            if abs > max_abs {
-                max_abs = abs;
-            }
+// Layout and tags of a decoded stream
+#[derive(Debug, Clone, Default)]
+pub struct StreamInfo {
+    pub total_samples: u64,
+    pub sample_rate: u32,
+    pub bit_depth: u32,
+    pub channels: u32,
+    // Vorbis comments (FLAC) or LIST/INFO chunk (WAV); keys are uppercased
+    pub tags: BTreeMap<String, String>,
+}
+
+// Consumer of the interleaved samples of one file, widened to i32. A trait rather than a
+// closure so every decoder/consumer pair is monomorphized: this is the hot loop of a scan.
+pub trait SampleSink {
+    type Output;
+    fn consume<I: Iterator<Item = i32>>(self, info: &StreamInfo, samples: I) -> Self::Output;
+}
+
+// Peak and RMS(dB) of the whole file, and the stats of every channel
+type LevelMetrics = (f32, f64, Vec<ChannelStats>);
 
-            let normalized = sample as f64 / max_amplitude;
-            squared_sum += normalized * normalized;
-            count += 1;
+#[derive(Clone, Copy)]
+struct Channel {
+    max_abs: i32,
+    squared_sum: f64,
+    count: u64,
+    hash: u64,
+    abs_hash: u64,
+}
+
+// FNV-1a over whole samples, like store::content_key does over bytes
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+// Metrics of the samples at one bit depth: `shift` low bits are dropped first, so a padded
+// 24-bit file measured at 16 bits gives exactly the figures of its 16-bit master
+struct MetricsView {
+    bit_depth: i32,
+    shift: u32,
+    max_amplitude: i32,
+    scale: f64,
+    max_abs: i32,
+    squared_sum: f64,
+    count: u64,
+    per_channel: Vec<Channel>,
+    index: usize,
+}
+
+impl MetricsView {
+    fn new(bit_depth: i32, shift: u32, channels: usize) -> Option<Self> {
+        let max_amplitude = AudioFile::get_max_amplitude(bit_depth);
+        if max_amplitude <= 0 {
+            return None;
         }
+        Some(MetricsView {
+            bit_depth,
+            shift,
+            max_amplitude,
+            scale: max_amplitude as f64,
+            max_abs: 0,
+            squared_sum: 0.0,
+            count: 0,
+            per_channel: vec![
+                Channel {
+                    max_abs: 0,
+                    squared_sum: 0.0,
+                    count: 0,
+                    hash: FNV_OFFSET,
+                    abs_hash: FNV_OFFSET,
+                };
+                channels.max(1)
+            ],
+            index: 0,
+        })
+    }
 
-        let peak_level = if max_abs == 0 {
-            0.0
+    // The whole-file figures are part of the exact group key, so they are accumulated exactly
+    // as before per-channel stats existed: same clipping, same summation order
+    #[inline]
+    fn push(&mut self, sample: i32) {
+        let sample = sample >> self.shift;
+        let abs = sample.saturating_abs();
+        if abs > self.max_abs {
+            self.max_abs = abs;
+        }
+        let normalized = sample as f64 / self.scale;
+        self.squared_sum += normalized * normalized;
+        self.count += 1;
+
+        let channel_count = self.per_channel.len();
+        let channel = &mut self.per_channel[self.index];
+        self.index = if self.index + 1 == channel_count {
+            0
         } else {
-            max_abs as f32 / Self::get_max_amplitude(bit_depth) as f32
+            self.index + 1
         };
+        // Inverting the most negative sample clips it to the maximum, which must still match
+        let abs = abs.min(self.max_amplitude);
+        if abs > channel.max_abs {
+            channel.max_abs = abs;
+        }
+        channel.squared_sum += normalized * normalized;
+        channel.count += 1;
+        channel.hash = (channel.hash ^ u64::from(sample as u32)).wrapping_mul(FNV_PRIME);
+        channel.abs_hash = (channel.abs_hash ^ u64::from(abs as u32)).wrapping_mul(FNV_PRIME);
+    }
 
-        let rms_db_level = if count == 0 {
-            default_rms_db_level()
-        } else {
+    fn finish(self) -> LevelMetrics {
+        let peak = |max_abs: i32| {
+            if max_abs == 0 {
+                0.0
+            } else {
+                max_abs as f32 / self.max_amplitude as f32
+            }
+        };
+        let rms_db = |squared_sum: f64, count: u64| {
+            if count == 0 {
+                return default_rms_db_level();
+            }
             let rms_amplitude = (squared_sum / count as f64).sqrt();
             if rms_amplitude > 0.0 {
                 20.0 * rms_amplitude.log10()
@@ -719,23 +1471,97 @@ impl AudioFile {
             }
         };
 
-        (peak_level, rms_db_level)
+        let channel_stats = self
+            .per_channel
+            .iter()
+            .map(|c| ChannelStats {
+                peak_level: peak(c.max_abs),
+                rms_db_level: clean_rms_db_level(rms_db(c.squared_sum, c.count)),
+                hash: c.hash,
+                abs_hash: c.abs_hash,
+            })
+            .collect();
+        (
+            peak(self.max_abs),
+            rms_db(self.squared_sum, self.count),
+            channel_stats,
+        )
     }
+}
 
-    fn get_max_amplitude(bit_depth: i32) -> i32 {
-        match bit_depth {
-            8 => i8::MAX as i32,
-            16 => i16::MAX as i32,
-            24 => (1 << 23) - 1,
-            32 => i32::MAX,
-            _ => 0,
-        }
+// Metrics of the exact group key, measured at the effective bit depth, plus the spectral
+// lossless check that bit depth comes from
+struct Metrics;
+
+impl SampleSink for Metrics {
+    type Output = (u32, LevelMetrics, LosslessCheck);
+
+    fn consume<I: Iterator<Item = i32>>(self, info: &StreamInfo, samples: I) -> Self::Output {
+        let mut probe = lossless::Probe::new(info);
+        let views = AudioFile::accumulate_metrics(
+            samples.inspect(|&sample| probe.push(sample)),
+            info.bit_depth as i32,
+            info.channels as usize,
+        );
+        let check = probe.finish();
+        let effective_bit_depth = check.effective_bit_depth(info.bit_depth);
+        let (bit_depth, metrics) = views
+            .into_iter()
+            .rev()
+            .find(|(depth, _)| *depth >= effective_bit_depth)
+            .expect("the file's own bit depth is always measured");
+        (bit_depth, metrics, check)
     }
+}
 
-    fn load_flac(path: &Path) -> Result<claxon::FlacReader<File>, ProcessError> {
-        let flac_file = File::open(path)?;
-        let reader = claxon::FlacReader::new(flac_file)?;
-        Ok(reader)
+// Decode a FLAC or WAV file into `sink`
+pub fn decode_samples<S: SampleSink>(
+    path: &Path,
+    sink: S,
+) -> Result<(StreamInfo, S::Output), ProcessError> {
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+    match extension {
+        "flac" => {
+            let mut reader = AudioFile::load_flac(path)?;
+            let stream_info = reader.streaminfo();
+            let info = StreamInfo {
+                total_samples: stream_info.samples.ok_or(ProcessError::NoSamplesFound)?,
+                sample_rate: stream_info.sample_rate,
+                bit_depth: stream_info.bits_per_sample,
+                channels: stream_info.channels,
+                tags: reader
+                    .tags()
+                    .map(|(name, value)| (name.to_ascii_uppercase(), value.to_string()))
+                    .collect(),
+            };
+            let output = sink.consume(&info, reader.samples().map(|sample| sample.unwrap_or(0)));
+            Ok((info, output))
+        }
+        "wav" => {
+            let mut reader = WavReader::open(path).map_err(|_| ProcessError::NonFlacError)?;
+            let spec = reader.spec();
+            let info = StreamInfo {
+                total_samples: reader.duration() as u64,
+                sample_rate: spec.sample_rate,
+                bit_depth: spec.bits_per_sample as u32,
+                channels: spec.channels as u32,
+                // Tags are best effort, a malformed LIST chunk must not fail the whole file
+                tags: read_wav_info_tags(path).unwrap_or_default(),
+            };
+
+            // Read with the correct sample width so 24/32-bit WAVs are handled correctly
+            let output = match spec.bits_per_sample {
+                8 => sink.consume(&info, reader.samples::<i8>().map(|s| s.unwrap_or(0) as i32)),
+                16 => sink.consume(
+                    &info,
+                    reader.samples::<i16>().map(|s| s.unwrap_or(0) as i32),
+                ),
+                24 | 32 => sink.consume(&info, reader.samples::<i32>().map(|s| s.unwrap_or(0))),
+                _ => return Err(ProcessError::UnsupportedBitDepth),
+            };
+            Ok((info, output))
+        }
+        _ => Err(ProcessError::UnsupportedBitDepth),
     }
 }
 
@@ -772,6 +1598,85 @@ impl From<claxon::Error> for ProcessError {
     }
 }
 
+// hound ignores metadata chunks, so walk the RIFF chunk list ourselves and decode LIST/INFO
+fn read_wav_info_tags(path: &Path) -> std::io::Result<BTreeMap<String, String>> {
+    let mut file = File::open(path)?;
+    let mut header = [0u8; 12];
+    file.read_exact(&mut header)?;
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return Ok(BTreeMap::new());
+    }
+
+    let mut tags = BTreeMap::new();
+    let mut chunk_header = [0u8; 8];
+    while file.read_exact(&mut chunk_header).is_ok() {
+        let id = [
+            chunk_header[0],
+            chunk_header[1],
+            chunk_header[2],
+            chunk_header[3],
+        ];
+        let len = u32::from_le_bytes([
+            chunk_header[4],
+            chunk_header[5],
+            chunk_header[6],
+            chunk_header[7],
+        ]) as u64;
+        // Chunks are word aligned
+        let padded = len + (len & 1);
+
+        if &id != b"LIST" || len < 4 {
+            file.seek(SeekFrom::Current(padded as i64))?;
+            continue;
+        }
+
+        // The length comes from the file: read through take() so a bogus one cannot size the buffer
+        let mut body = Vec::new();
+        (&mut file).take(len).read_to_end(&mut body)?;
+        if (body.len() as u64) < len {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        if len != padded {
+            file.seek(SeekFrom::Current(1))?;
+        }
+        if &body[0..4] != b"INFO" {
+            continue;
+        }
+
+        let mut pos = 4usize;
+        while pos + 8 <= body.len() {
+            let sub_id = &body[pos..pos + 4];
+            let sub_len =
+                u32::from_le_bytes([body[pos + 4], body[pos + 5], body[pos + 6], body[pos + 7]])
+                    as usize;
+            let start = pos + 8;
+            let end = (start + sub_len).min(body.len());
+            let value = String::from_utf8_lossy(&body[start..end])
+                .trim_end_matches('\0')
+                .trim()
+                .to_string();
+
+            let key = match sub_id {
+                b"INAM" => "TITLE",
+                b"IART" => "ARTIST",
+                b"IPRD" => "ALBUM",
+                b"ICRD" => "DATE",
+                b"IGNR" => "GENRE",
+                b"ITRK" | b"IPRT" => "TRACKNUMBER",
+                b"ICMT" => "COMMENT",
+                b"ISFT" => "ENCODER",
+                _ => "",
+            };
+            if !key.is_empty() && !value.is_empty() {
+                tags.insert(key.to_string(), value);
+            }
+            pos = start + sub_len + (sub_len & 1);
+        }
+    }
+
+    Ok(tags)
+}
+
 fn backup_broken(path: &Path, reason: &str) {
     let broken = if let Some(ext) = path.extension() {
         let mut new_ext = OsString::from(ext);
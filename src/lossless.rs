@@ -0,0 +1,269 @@
+use crate::{AudioFile, StreamInfo};
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// The spectrum is averaged over 4096-sample windows taken about once a second: enough to see
+// where the content of a track stops, cheap enough to run during every scan
+const WINDOW_LEN: usize = 4096;
+// Bins are averaged in bands (86 Hz at 44.1 kHz) before looking for a cutoff
+const BAND_BINS: usize = 8;
+// A lossy encoder's lowpass is a cliff: the spectrum drops this far within a few bands and stays
+// down up to Nyquist. Natural roll-off and band-limited recordings slope far more gently.
+const CLIFF_DB: f64 = 25.0;
+const TRANSITION_BANDS: usize = 4;
+// Lowpass range of MP3/AAC/Vorbis encoders, from low bitrates up to 320 kbit/s. Anti-alias
+// filters of genuine masters sit closer to Nyquist.
+const LOSSY_CUTOFF_HZ: (f64, f64) = (10_000.0, 20_500.0);
+const MAX_LOSSY_CUTOFF_SHARE: f64 = 0.95;
+// Fewer non-silent windows than this and the verdict is left open
+const MIN_WINDOWS: usize = 5;
+// Rates an upsampled file may have been made from, lowest first
+const SOURCE_RATES: [u32; 6] = [44_100, 48_000, 88_200, 96_000, 176_400, 192_000];
+// The cutoff is only known to a band, and resamplers leave a little content above Nyquist
+const CUTOFF_TOLERANCE: f64 = 1.02;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Verdict {
+    // Content up to the top of the spectrum, every bit in use
+    Lossless,
+    // Spectrum cut off like a lossy encoder's lowpass: a decoded MP3/AAC saved as lossless
+    LossyTranscode,
+    // Genuine audio whose low bits are zero in every sample: a 16-bit master stored as 24-bit
+    PaddedBitDepth,
+    // Too short or too quiet to tell
+    Inconclusive,
+}
+
+impl Verdict {
+    pub const ALL: [Verdict; 4] = [
+        Verdict::Lossless,
+        Verdict::LossyTranscode,
+        Verdict::PaddedBitDepth,
+        Verdict::Inconclusive,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Verdict::Lossless => "lossless",
+            Verdict::LossyTranscode => "lossy-transcode",
+            Verdict::PaddedBitDepth => "padded-bit-depth",
+            Verdict::Inconclusive => "inconclusive",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Verdict> {
+        Verdict::ALL.into_iter().find(|v| v.name() == name)
+    }
+}
+
+// What the spectral pass of a scan found out about a file
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LosslessCheck {
+    pub verdict: Verdict,
+    // Where the content stops in Hz: a lowpass cliff, or Nyquist. 0 when inconclusive.
+    pub cutoff_hz: u32,
+    // Low bits that are zero in every sample
+    pub padded_bits: u32,
+}
+
+impl LosslessCheck {
+    // Report label of a suspicious file, e.g. "lossy transcode, cutoff 16.0 kHz"
+    pub fn describe(&self, bit_depth: u32) -> Option<String> {
+        match self.verdict {
+            Verdict::LossyTranscode => Some(format!(
+                "lossy transcode, cutoff {:.1} kHz",
+                self.cutoff_hz as f64 / 1000.0
+            )),
+            Verdict::PaddedBitDepth => Some(format!(
+                "{bit_depth}-bit padded from {}",
+                bit_depth.saturating_sub(self.padded_bits)
+            )),
+            Verdict::Lossless | Verdict::Inconclusive => None,
+        }
+    }
+
+    // Smallest of 16 and 24 bits that holds every sample of a file with more bits than that
+    pub fn effective_bit_depth(&self, bit_depth: u32) -> u32 {
+        [16, 24]
+            .into_iter()
+            .find(|&depth| depth < bit_depth && bit_depth - depth <= self.padded_bits)
+            .unwrap_or(bit_depth)
+    }
+
+    // Lowest standard rate whose Nyquist frequency covers the content of a hi-res file (above
+    // 48 kHz), such as 44.1 kHz for a CD upsampled to 96 kHz
+    pub fn effective_sample_rate(&self, sample_rate: u32) -> u32 {
+        if sample_rate <= 48_000 || self.verdict == Verdict::Inconclusive {
+            return sample_rate;
+        }
+        SOURCE_RATES
+            .into_iter()
+            .find(|&rate| {
+                rate < sample_rate && self.cutoff_hz as f64 <= rate as f64 / 2.0 * CUTOFF_TOLERANCE
+            })
+            .unwrap_or(sample_rate)
+    }
+}
+
+// How much a keep policy trusts a file to be the genuine master: checked lossless first, then
+// files nothing is known about, then padded copies, then lossy transcodes
+pub fn authenticity_rank(file: &AudioFile) -> u8 {
+    match file.lossless.map(|check| check.verdict) {
+        Some(Verdict::Lossless) => 3,
+        Some(Verdict::Inconclusive) | None => 2,
+        Some(Verdict::PaddedBitDepth) => 1,
+        Some(Verdict::LossyTranscode) => 0,
+    }
+}
+
+// Fed every interleaved sample of a file during the scan's metrics pass
+pub(crate) struct Probe {
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    sample_rate: u32,
+    bit_depth: u32,
+    channels: usize,
+    // Normalizes the channel sum to a mono sample in [-1, 1]
+    scale: f32,
+    // Frames from the start of one analysis window to the next
+    stride: usize,
+    frame: usize,
+    channel: usize,
+    frame_sum: f32,
+    pending: Vec<f32>,
+    buffer: Vec<Complex<f32>>,
+    power: Vec<f64>,
+    windows: usize,
+    // OR of every sample: its trailing zeros are the padded bits
+    used_bits: u32,
+}
+
+impl Probe {
+    pub(crate) fn new(info: &StreamInfo) -> Self {
+        let fft = FftPlanner::new().plan_fft_forward(WINDOW_LEN);
+        let window = (0..WINDOW_LEN)
+            .map(|i| {
+                let phase = 2.0 * std::f32::consts::PI * i as f32 / WINDOW_LEN as f32;
+                0.5 - 0.5 * phase.cos()
+            })
+            .collect();
+        let channels = info.channels.max(1) as usize;
+        let full_scale = (1u64 << info.bit_depth.clamp(1, 32).saturating_sub(1)) as f32;
+        Probe {
+            fft,
+            window,
+            sample_rate: info.sample_rate,
+            bit_depth: info.bit_depth,
+            channels,
+            scale: 1.0 / (full_scale * channels as f32),
+            stride: (info.sample_rate as usize).max(WINDOW_LEN),
+            frame: 0,
+            channel: 0,
+            frame_sum: 0.0,
+            pending: Vec::with_capacity(WINDOW_LEN),
+            buffer: vec![Complex::new(0.0, 0.0); WINDOW_LEN],
+            power: vec![0.0; WINDOW_LEN / 2],
+            windows: 0,
+            used_bits: 0,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn push(&mut self, sample: i32) {
+        // Two's complement keeps the trailing zeros of negative samples
+        self.used_bits |= sample as u32;
+        self.frame_sum += sample as f32;
+        self.channel += 1;
+        if self.channel < self.channels {
+            return;
+        }
+        let mono = self.frame_sum * self.scale;
+        (self.frame_sum, self.channel) = (0.0, 0);
+        let offset = self.frame % self.stride;
+        self.frame += 1;
+        if offset < WINDOW_LEN {
+            self.pending.push(mono);
+            if self.pending.len() == WINDOW_LEN {
+                self.analyse_window();
+                self.pending.clear();
+            }
+        }
+    }
+
+    fn analyse_window(&mut self) {
+        if self.pending.iter().all(|&sample| sample == 0.0) {
+            return;
+        }
+        for (slot, (sample, weight)) in self
+            .buffer
+            .iter_mut()
+            .zip(self.pending.iter().zip(&self.window))
+        {
+            *slot = Complex::new(sample * weight, 0.0);
+        }
+        self.fft.process(&mut self.buffer);
+        for (power, bin) in self.power.iter_mut().zip(&self.buffer) {
+            *power += f64::from(bin.norm_sqr());
+        }
+        self.windows += 1;
+    }
+
+    pub(crate) fn finish(self) -> LosslessCheck {
+        let padded_bits = if self.used_bits == 0 {
+            0
+        } else {
+            self.used_bits
+                .trailing_zeros()
+                .min(self.bit_depth.saturating_sub(1))
+        };
+        if self.windows < MIN_WINDOWS {
+            return LosslessCheck {
+                verdict: Verdict::Inconclusive,
+                cutoff_hz: 0,
+                padded_bits,
+            };
+        }
+
+        let levels: Vec<f64> = self
+            .power
+            .chunks(BAND_BINS)
+            .map(|band| {
+                let mean = band.iter().sum::<f64>() / (band.len() * self.windows) as f64;
+                10.0 * (mean + 1e-30).log10()
+            })
+            .collect();
+        // Loudest band at or above each band
+        let mut loudest_above = levels.clone();
+        for band in (0..levels.len().saturating_sub(1)).rev() {
+            loudest_above[band] = loudest_above[band].max(loudest_above[band + 1]);
+        }
+        // Highest band that stands CLIFF_DB over everything past the transition that follows it
+        let cliff = (2..levels.len().saturating_sub(TRANSITION_BANDS + 1))
+            .rev()
+            .find(|&band| {
+                let below = (levels[band - 2] + levels[band - 1] + levels[band]) / 3.0;
+                below - loudest_above[band + TRANSITION_BANDS + 1] >= CLIFF_DB
+            });
+        let nyquist = self.sample_rate as f64 / 2.0;
+        let band_hz = self.sample_rate as f64 / WINDOW_LEN as f64 * BAND_BINS as f64;
+        let cutoff_hz = cliff.map_or(nyquist, |band| (band + 1) as f64 * band_hz);
+
+        let lossy = (LOSSY_CUTOFF_HZ.0..=LOSSY_CUTOFF_HZ.1).contains(&cutoff_hz)
+            && cutoff_hz < nyquist * MAX_LOSSY_CUTOFF_SHARE;
+        let verdict = if lossy {
+            Verdict::LossyTranscode
+        } else if self.bit_depth > 16 && padded_bits > 0 {
+            Verdict::PaddedBitDepth
+        } else {
+            Verdict::Lossless
+        };
+        LosslessCheck {
+            verdict,
+            cutoff_hz: cutoff_hz.round() as u32,
+            padded_bits,
+        }
+    }
+}
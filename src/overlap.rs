@@ -0,0 +1,340 @@
+use crate::{AudioFile, ProcessError, SampleSink, StreamInfo, decode_samples};
+use rayon::prelude::*;
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+// Fingerprints are taken from a mono downmix resampled to this rate, so files of any sample rate
+// line up frame for frame. Everything the hash looks at is below 3 kHz.
+const FINGERPRINT_RATE: f64 = 5512.5;
+// 371 ms analysis window, advanced by 11.6 ms: neighbouring frames overlap by 97%, so a track
+// starting anywhere inside a mix still lands close to one of our frame positions
+const FRAME_LEN: usize = 2048;
+const HOP_LEN: usize = 64;
+// 33 log-spaced bands give the 32 bits of a frame hash
+const BANDS: usize = 33;
+const LOW_HZ: f64 = 300.0;
+const HIGH_HZ: f64 = 2000.0;
+
+// Only files this long are searched for other tracks (mixes, live sets, un-split images)
+pub const MIN_CONTAINER_SECS: f64 = 600.0;
+// Shorter files are too generic to place reliably
+const MIN_TRACK_SECS: f64 = 30.0;
+// Exact 32-bit hash matches are rare once a track is mixed, so the index is keyed on each half
+// of a hash: one flipped bit still leaves the other half to vote
+fn index_keys(hash: u32) -> [u32; 2] {
+    [hash >> 16, (1 << 16) | (hash & 0xffff)]
+}
+
+// Every INDEX_STRIDE-th container frame goes into the lookup index; the query side looks up
+// all of its frames, so any alignment still gets votes
+const INDEX_STRIDE: usize = 4;
+// Hashes this common (silence, steady tones) say nothing about where a track is
+const MAX_POSTINGS: usize = 2000;
+const MIN_VOTES: u32 = 8;
+// Alignment is verified per block of frames (about 3 s): a block matches when less than this
+// share of its bits differ. Unrelated audio sits around 0.5.
+const BLOCK_FRAMES: usize = 256;
+const MAX_BIT_ERROR_RATE: f64 = 0.3;
+// Share of a track that has to be found for it to be reported; DJs cut intros and outros
+const MIN_MATCHED_SHARE: f64 = 0.5;
+
+// Sub-band energy hash of every frame of a file
+#[derive(Debug, Clone, Default)]
+pub struct Fingerprint {
+    pub hashes: Vec<u32>,
+}
+
+pub fn frame_secs() -> f64 {
+    HOP_LEN as f64 / FINGERPRINT_RATE
+}
+
+impl Fingerprint {
+    pub fn from_path(path: &Path) -> Result<Fingerprint, ProcessError> {
+        let (_, fingerprint) = decode_samples(path, Fingerprinter::new())?;
+        Ok(fingerprint)
+    }
+
+    pub fn duration_secs(&self) -> f64 {
+        self.hashes.len() as f64 * frame_secs()
+    }
+}
+
+struct Fingerprinter {
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    // FFT bin range of every band
+    bands: Vec<(usize, usize)>,
+}
+
+impl Fingerprinter {
+    fn new() -> Self {
+        let fft = FftPlanner::new().plan_fft_forward(FRAME_LEN);
+        let window = (0..FRAME_LEN)
+            .map(|i| {
+                let phase = 2.0 * std::f32::consts::PI * i as f32 / FRAME_LEN as f32;
+                0.5 - 0.5 * phase.cos()
+            })
+            .collect();
+        let bin_hz = FINGERPRINT_RATE / FRAME_LEN as f64;
+        let edge = |band: usize| {
+            let hz = LOW_HZ * (HIGH_HZ / LOW_HZ).powf(band as f64 / BANDS as f64);
+            (hz / bin_hz).round() as usize
+        };
+        let bands = (0..BANDS)
+            .map(|band| (edge(band), edge(band + 1)))
+            .collect();
+        Fingerprinter { fft, window, bands }
+    }
+
+    fn band_energies(&self, frame: &[f32], buffer: &mut [Complex<f32>]) -> [f32; BANDS] {
+        for (slot, (sample, weight)) in buffer.iter_mut().zip(frame.iter().zip(&self.window)) {
+            *slot = Complex::new(sample * weight, 0.0);
+        }
+        self.fft.process(buffer);
+        let mut energies = [0f32; BANDS];
+        for (energy, &(from, to)) in energies.iter_mut().zip(&self.bands) {
+            *energy = buffer[from..to].iter().map(|bin| bin.norm_sqr()).sum();
+        }
+        energies
+    }
+}
+
+// Bit b is set when the energy difference between bands b and b+1 grew since the last frame
+fn frame_hash(previous: &[f32; BANDS], current: &[f32; BANDS]) -> u32 {
+    (0..BANDS - 1).fold(0, |hash, band| {
+        let now = current[band] - current[band + 1];
+        let before = previous[band] - previous[band + 1];
+        (hash << 1) | u32::from(now - before > 0.0)
+    })
+}
+
+impl SampleSink for Fingerprinter {
+    type Output = Fingerprint;
+
+    fn consume<I: Iterator<Item = i32>>(self, info: &StreamInfo, samples: I) -> Fingerprint {
+        let channels = info.channels.max(1) as usize;
+        let scale = 1.0 / ((1u64 << info.bit_depth.clamp(1, 32).saturating_sub(1)) as f64);
+        // Box-filter resampler: every output sample is the mean of the input it spans
+        let step = info.sample_rate as f64 / FINGERPRINT_RATE;
+
+        let mut hashes = Vec::new();
+        let mut pending: Vec<f32> = Vec::with_capacity(FRAME_LEN + HOP_LEN);
+        let mut buffer = vec![Complex::new(0f32, 0f32); FRAME_LEN];
+        let mut previous: Option<[f32; BANDS]> = None;
+
+        let (mut frame_sum, mut channel) = (0f64, 0);
+        let (mut acc, mut acc_count, mut position, mut next_edge) = (0f64, 0u32, 0f64, step);
+        for sample in samples {
+            frame_sum += sample as f64;
+            channel += 1;
+            if channel < channels {
+                continue;
+            }
+            acc += frame_sum * scale / channels as f64;
+            acc_count += 1;
+            (frame_sum, channel) = (0.0, 0);
+            position += 1.0;
+            if position < next_edge {
+                continue;
+            }
+            next_edge += step;
+            pending.push((acc / acc_count as f64) as f32);
+            (acc, acc_count) = (0.0, 0);
+
+            if pending.len() == FRAME_LEN {
+                let energies = self.band_energies(&pending, &mut buffer);
+                if let Some(previous) = previous.as_ref() {
+                    hashes.push(frame_hash(previous, &energies));
+                }
+                previous = Some(energies);
+                pending.drain(..HOP_LEN);
+            }
+        }
+        Fingerprint { hashes }
+    }
+}
+
+// A track found inside a longer file
+#[derive(Debug, Clone, Serialize)]
+pub struct Overlap {
+    pub track: String,
+    pub container: String,
+    // Where the track starts in the container
+    pub offset_secs: f64,
+    // How much of the track was found there
+    pub matched_secs: f64,
+    pub track_secs: f64,
+}
+
+impl Overlap {
+    // "a.flac at 12:34 in mix.flac (4:56 of 5:02 matched)"
+    pub fn describe(&self) -> String {
+        format!(
+            "{} at {} in {} ({} of {} matched)",
+            self.track,
+            format_clock(self.offset_secs),
+            self.container,
+            format_clock(self.matched_secs),
+            format_clock(self.track_secs)
+        )
+    }
+}
+
+fn format_clock(secs: f64) -> String {
+    let secs = secs.max(0.0).round() as u64;
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+// Find every file of at least MIN_TRACK_SECS that occurs inside a longer file of at least
+// MIN_CONTAINER_SECS. Files are decoded again: fingerprints are not kept in the state file.
+// Files that fail to decode are passed to `on_error` and left out.
+pub fn find_overlaps(
+    audio_files: &[AudioFile],
+    on_error: &(dyn Fn(&str, &ProcessError) + Sync),
+) -> Vec<Overlap> {
+    let mut containers: Vec<&AudioFile> = audio_files
+        .iter()
+        .filter(|f| f.duration_secs() >= MIN_CONTAINER_SECS)
+        .collect();
+    containers.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+    containers.dedup_by(|a, b| a.file_path == b.file_path);
+    let Some(longest) = containers
+        .iter()
+        .map(|f| f.duration_secs())
+        .max_by(f64::total_cmp)
+    else {
+        return Vec::new();
+    };
+
+    let fingerprint = |file: &AudioFile| match Fingerprint::from_path(Path::new(&file.file_path)) {
+        Ok(fingerprint) => Some(fingerprint),
+        Err(err) => {
+            on_error(&file.file_path, &err);
+            None
+        }
+    };
+    let containers: Vec<(&AudioFile, Fingerprint)> = containers
+        .into_par_iter()
+        .filter_map(|file| fingerprint(file).map(|fp| (file, fp)))
+        .collect();
+
+    let mut index: HashMap<u32, Vec<(u32, u32)>> = HashMap::new();
+    for (container_idx, (_, fp)) in containers.iter().enumerate() {
+        for (frame, &hash) in fp.hashes.iter().enumerate().step_by(INDEX_STRIDE) {
+            for key in index_keys(hash) {
+                index
+                    .entry(key)
+                    .or_default()
+                    .push((container_idx as u32, frame as u32));
+            }
+        }
+    }
+    index.retain(|_, postings| postings.len() <= MAX_POSTINGS);
+
+    let mut tracks: Vec<&AudioFile> = audio_files
+        .iter()
+        .filter(|f| f.duration_secs() >= MIN_TRACK_SECS && f.duration_secs() < longest)
+        .collect();
+    tracks.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+    tracks.dedup_by(|a, b| a.file_path == b.file_path);
+
+    let mut overlaps: Vec<Overlap> = tracks
+        .into_par_iter()
+        .flat_map_iter(|track| {
+            let found = fingerprint(track)
+                .map(|fp| locate(track, &fp, &containers, &index))
+                .unwrap_or_default();
+            found.into_iter()
+        })
+        .collect();
+    overlaps.sort_by(|a, b| {
+        a.container
+            .cmp(&b.container)
+            .then(a.offset_secs.total_cmp(&b.offset_secs))
+    });
+    overlaps
+}
+
+// Vote for (container, offset) pairs through the index, then verify the best offset of each
+// container block by block
+fn locate(
+    track: &AudioFile,
+    fp: &Fingerprint,
+    containers: &[(&AudioFile, Fingerprint)],
+    index: &HashMap<u32, Vec<(u32, u32)>>,
+) -> Vec<Overlap> {
+    let mut votes: HashMap<(u32, i64), u32> = HashMap::new();
+    for (frame, &hash) in fp.hashes.iter().enumerate() {
+        let postings = index_keys(hash)
+            .into_iter()
+            .filter_map(|key| index.get(&key));
+        for &(container_idx, container_frame) in postings.flatten() {
+            let offset = container_frame as i64 - frame as i64;
+            *votes.entry((container_idx, offset)).or_default() += 1;
+        }
+    }
+
+    let mut best: HashMap<u32, (i64, u32)> = HashMap::new();
+    for ((container_idx, offset), count) in votes {
+        let entry = best.entry(container_idx).or_insert((offset, 0));
+        if count > entry.1 {
+            *entry = (offset, count);
+        }
+    }
+
+    let mut found = Vec::new();
+    for (container_idx, (offset, count)) in best {
+        let (container, container_fp) = &containers[container_idx as usize];
+        if count < MIN_VOTES
+            || container.file_path == track.file_path
+            || container_fp.hashes.len() <= fp.hashes.len()
+        {
+            continue;
+        }
+        let matched_frames = matched_frames(&fp.hashes, &container_fp.hashes, offset);
+        let matched_secs = matched_frames as f64 * frame_secs();
+        if matched_secs >= MIN_MATCHED_SHARE * fp.duration_secs() {
+            found.push(Overlap {
+                track: track.file_path.clone(),
+                container: container.file_path.clone(),
+                offset_secs: offset.max(0) as f64 * frame_secs(),
+                matched_secs,
+                track_secs: track.duration_secs(),
+            });
+        }
+    }
+    found
+}
+
+// Frames of `track` in blocks whose bit error rate against `container` (shifted by `offset`
+// frames) is low enough
+fn matched_frames(track: &[u32], container: &[u32], offset: i64) -> usize {
+    let mut matched = 0;
+    for (block_idx, block) in track.chunks(BLOCK_FRAMES).enumerate() {
+        let start = offset + (block_idx * BLOCK_FRAMES) as i64;
+        let (mut differing, mut compared) = (0u32, 0usize);
+        for (frame, hash) in block.iter().enumerate() {
+            let Ok(at) = usize::try_from(start + frame as i64) else {
+                continue;
+            };
+            let Some(other) = container.get(at) else {
+                break;
+            };
+            differing += (hash ^ other).count_ones();
+            compared += 1;
+        }
+        // A partial block at either end of the container only counts for what was compared
+        if compared > 0 && (differing as f64) < MAX_BIT_ERROR_RATE * (32 * compared) as f64 {
+            matched += compared;
+        }
+    }
+    matched
+}
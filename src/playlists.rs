@@ -0,0 +1,238 @@
+use crate::groups::DuplicateGroup;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use walkdir::WalkDir;
+
+// One .m3u8 per group so candidates can be A/B'd in any player. Returns the number written.
+pub fn write_group_playlists(dir: &Path, groups: &[DuplicateGroup]) -> std::io::Result<usize> {
+    std::fs::create_dir_all(dir)?;
+    let width = groups.len().to_string().len().max(4);
+
+    for (idx, group) in groups.iter().enumerate() {
+        let path = dir.join(format!("group-{:0width$}.m3u8", idx + 1));
+        let mut out = std::io::BufWriter::new(std::fs::File::create(&path)?);
+        writeln!(out, "#EXTM3U")?;
+        for file in &group.files {
+            let label = match file.tag_label() {
+                label if !label.is_empty() => label,
+                _ => Path::new(&file.file_path)
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+            };
+            // The title shows which copy is playing, so prefix it with format and size
+            writeln!(
+                out,
+                "#EXTINF:{},[{} {}bit {}] {label}",
+                file.duration_secs().round() as u64,
+                file.format(),
+                file.bit_depth,
+                crate::format_size_bytes(file.file_size)
+            )?;
+            writeln!(out, "{}", file.file_path)?;
+        }
+        out.flush()?;
+    }
+    Ok(groups.len())
+}
+
+#[derive(Debug, Default)]
+pub struct RelinkSummary {
+    pub playlists_scanned: usize,
+    pub playlists_changed: usize,
+    pub entries_rewritten: usize,
+    pub errors: Vec<String>,
+}
+
+fn is_playlist(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| {
+            let ext = ext.to_string_lossy().to_ascii_lowercase();
+            ext == "m3u" || ext == "m3u8" || ext == "pls"
+        })
+        .unwrap_or(false)
+}
+
+// Resolve "." and ".." without touching the disk, the target may not exist anymore
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+// Relative path from `base` (a directory) to `target`, both absolute
+fn relative_to(target: &Path, base: &Path) -> PathBuf {
+    let target: Vec<Component> = target.components().collect();
+    let base: Vec<Component> = base.components().collect();
+    let common = target
+        .iter()
+        .zip(base.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut out = PathBuf::new();
+    for _ in common..base.len() {
+        out.push("..");
+    }
+    for component in &target[common..] {
+        out.push(component);
+    }
+    out
+}
+
+// Follow kept-file chains (A replaced by B, later B replaced by C), guarding against cycles
+fn resolve<'a>(replaced: &'a HashMap<String, String>, path: &str) -> Option<&'a str> {
+    let mut current = replaced.get(path)?;
+    for _ in 0..replaced.len() {
+        match replaced.get(current) {
+            Some(next) => current = next,
+            None => break,
+        }
+    }
+    Some(current)
+}
+
+// Rewrite one entry if it points at a replaced file, keeping relative entries relative
+fn relink_entry(
+    entry: &str,
+    playlist_dir: &Path,
+    replaced: &HashMap<String, String>,
+) -> Option<String> {
+    let trimmed = entry.trim();
+    if trimmed.is_empty() || trimmed.contains("://") {
+        return None;
+    }
+    let entry_path = Path::new(trimmed);
+    let absolute = if entry_path.is_absolute() {
+        normalize(entry_path)
+    } else {
+        normalize(&playlist_dir.join(entry_path))
+    };
+    let kept = resolve(replaced, &absolute.to_string_lossy())?;
+
+    if entry_path.is_absolute() {
+        Some(kept.to_string())
+    } else {
+        Some(
+            relative_to(Path::new(kept), playlist_dir)
+                .to_string_lossy()
+                .to_string(),
+        )
+    }
+}
+
+// Old .m3u files are often Latin-1; decode them byte-for-char so they can be written back unchanged
+fn decode(bytes: &[u8], latin1_ok: bool) -> Option<(String, bool)> {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => Some((text.to_string(), false)),
+        Err(_) if latin1_ok => Some((bytes.iter().map(|&b| b as char).collect(), true)),
+        Err(_) => None,
+    }
+}
+
+fn encode(text: &str, latin1: bool) -> Option<Vec<u8>> {
+    if !latin1 {
+        return Some(text.as_bytes().to_vec());
+    }
+    text.chars()
+        .map(|c| u8::try_from(u32::from(c)).ok())
+        .collect()
+}
+
+fn relink_playlist(
+    path: &Path,
+    replaced: &HashMap<String, String>,
+    dry_run: bool,
+) -> Result<usize, String> {
+    let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+    let is_m3u = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("m3u"));
+    let (text, latin1) = decode(&bytes, is_m3u).ok_or("not valid UTF-8")?;
+    let is_pls = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("pls"));
+    let playlist_dir = path.parent().unwrap_or(Path::new("/"));
+
+    let mut rewritten = 0;
+    let mut output = String::with_capacity(text.len());
+    for line in text.split_inclusive('\n') {
+        let body = line.trim_end_matches(['\r', '\n']);
+        let ending = &line[body.len()..];
+
+        let new_body = if is_pls {
+            // PLS entries look like "File1=path"
+            body.split_once('=')
+                .filter(|(key, _)| key.trim().to_ascii_lowercase().starts_with("file"))
+                .and_then(|(key, value)| {
+                    relink_entry(value, playlist_dir, replaced).map(|v| format!("{key}={v}"))
+                })
+        } else if body.starts_with('#') {
+            None
+        } else {
+            relink_entry(body, playlist_dir, replaced)
+        };
+
+        match new_body {
+            Some(new_body) => {
+                rewritten += 1;
+                output.push_str(&new_body);
+            }
+            None => output.push_str(body),
+        }
+        output.push_str(ending);
+    }
+
+    if rewritten == 0 || dry_run {
+        return Ok(rewritten);
+    }
+
+    let encoded = encode(&output, latin1).ok_or("kept path cannot be written as Latin-1")?;
+    // Same temp + rename dance as the resume cache so a crash never leaves half a playlist
+    let tmp_path = path.with_extension("fadupes-tmp");
+    std::fs::write(&tmp_path, encoded).map_err(|e| e.to_string())?;
+    std::fs::rename(&tmp_path, path).map_err(|e| e.to_string())?;
+    Ok(rewritten)
+}
+
+// Point every M3U/M3U8/PLS entry under `roots` that references a replaced file at its kept file
+pub fn relink_playlists(
+    roots: &[PathBuf],
+    replaced: &HashMap<String, String>,
+    dry_run: bool,
+) -> RelinkSummary {
+    let mut summary = RelinkSummary::default();
+
+    for root in roots {
+        let root = std::fs::canonicalize(root).unwrap_or_else(|_| root.clone());
+        for entry in WalkDir::new(&root)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file() && is_playlist(e.path()))
+        {
+            summary.playlists_scanned += 1;
+            match relink_playlist(entry.path(), replaced, dry_run) {
+                Ok(0) => {}
+                Ok(count) => {
+                    summary.playlists_changed += 1;
+                    summary.entries_rewritten += count;
+                }
+                Err(err) => summary
+                    .errors
+                    .push(format!("{}: {err}", entry.path().display())),
+            }
+        }
+    }
+
+    summary
+}
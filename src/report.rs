@@ -0,0 +1,379 @@
+use crate::actions::KeepPolicy;
+use crate::groups::{AlbumMatch, DuplicateGroup, GroupKey, channel_relation, find_album_matches};
+use crate::overlap::Overlap;
+use crate::run::RunInfo;
+use crate::{AudioFile, ScanError, format_size_bytes};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+
+pub(crate) mod delimited;
+mod fdupes;
+mod html;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Csv,
+    Tsv,
+    Html,
+    // fdupes-compatible listing (-1 same-line mode, -S sizes)
+    Fdupes { same_line: bool, show_size: bool },
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            "tsv" => Ok(OutputFormat::Tsv),
+            "html" => Ok(OutputFormat::Html),
+            "fdupes" => Ok(OutputFormat::Fdupes {
+                same_line: false,
+                show_size: false,
+            }),
+            other => Err(format!(
+                "unknown format \"{other}\" (use text, json, csv, tsv, html or fdupes)"
+            )),
+        }
+    }
+}
+
+// Readable form of the exact grouping key
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportKey {
+    pub total_samples: u64,
+    pub sample_rate: u32,
+    pub bit_depth: u32,
+    pub channels: u32,
+    pub peak_level: f32,
+    pub rms_db_level: f64,
+}
+
+impl From<GroupKey> for ReportKey {
+    fn from(key: GroupKey) -> Self {
+        ReportKey {
+            total_samples: key.0,
+            sample_rate: key.1,
+            bit_depth: key.2,
+            channels: key.3,
+            peak_level: f32::from_bits(key.4),
+            rms_db_level: f64::from_bits(key.5),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportGroup {
+    pub id: usize,
+    pub key: ReportKey,
+    // Path of the file the keep policy would keep
+    pub kept: String,
+    pub reclaimable_bytes: u64,
+    pub files: Vec<AudioFile>,
+    // Path -> how it differs from the kept file ("channels swapped", "gain -3.00 dB",
+    // "lossy transcode, cutoff 16.0 kHz", ...)
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub relations: BTreeMap<String, String>,
+}
+
+impl ReportGroup {
+    // Role column of the CSV/TSV and HTML reports
+    pub fn role(&self, file: &AudioFile) -> &'static str {
+        if file.file_path == self.kept {
+            "kept"
+        } else if file.reference {
+            "reference"
+        } else {
+            "duplicate"
+        }
+    }
+}
+
+// What two input roots have in common, one cell of the root-pair matrix
+#[derive(Debug, Clone, Serialize)]
+pub struct RootPair {
+    pub a: String,
+    pub b: String,
+    // Duplicate groups with files under both roots
+    pub tracks: usize,
+    // One copy of each shared track (the smallest), i.e. what dropping either side would free
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportSummary {
+    pub files_scanned: usize,
+    pub groups: usize,
+    pub duplicate_files: usize,
+    pub reclaimable_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub run: RunInfo,
+    pub summary: ReportSummary,
+    pub groups: Vec<ReportGroup>,
+    pub errors: Vec<ScanError>,
+    // Tracks found inside longer files (--overlaps)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub overlaps: Vec<Overlap>,
+    // Directories that are copies or subsets of another one
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub albums: Vec<AlbumMatch>,
+    // Input roots seen in the groups and every pair of them, when there is more than one root
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub roots: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub root_pairs: Vec<RootPair>,
+}
+
+impl Report {
+    pub fn new(
+        run: RunInfo,
+        audio_files: &[AudioFile],
+        groups: &[DuplicateGroup],
+        errors: Vec<ScanError>,
+        policy: KeepPolicy,
+    ) -> Self {
+        let albums = find_album_matches(audio_files, groups);
+        let groups: Vec<ReportGroup> = groups
+            .iter()
+            .enumerate()
+            .map(|(idx, group)| {
+                let kept = &group.files[policy.choose(&group.files)];
+                // Reference files other than the kept one stay as well
+                let reclaimable_bytes = group
+                    .files
+                    .iter()
+                    .filter(|f| f.file_path != kept.file_path && !f.reference)
+                    .map(|f| f.file_size)
+                    .sum();
+                let relations = group
+                    .files
+                    .iter()
+                    .filter_map(|f| Some((f.file_path.clone(), relation(group, f, kept)?)))
+                    .collect();
+                ReportGroup {
+                    id: idx + 1,
+                    key: group.key.into(),
+                    kept: kept.file_path.clone(),
+                    reclaimable_bytes,
+                    files: group.files.clone(),
+                    relations,
+                }
+            })
+            .collect();
+
+        let summary = ReportSummary {
+            files_scanned: audio_files.len(),
+            groups: groups.len(),
+            duplicate_files: groups.iter().map(|g| g.files.len()).sum(),
+            reclaimable_bytes: groups.iter().map(|g| g.reclaimable_bytes).sum(),
+        };
+
+        let (roots, root_pairs) = root_matrix(&groups);
+        Report {
+            run,
+            summary,
+            groups,
+            errors,
+            overlaps: Vec::new(),
+            albums,
+            roots,
+            root_pairs,
+        }
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W, format: OutputFormat) -> std::io::Result<()> {
+        match format {
+            OutputFormat::Text => self.write_text(out),
+            OutputFormat::Json => {
+                serde_json::to_writer_pretty(&mut *out, self)?;
+                writeln!(out)
+            }
+            OutputFormat::Csv => delimited::write(self, out, delimited::Delimiter::Comma),
+            OutputFormat::Tsv => delimited::write(self, out, delimited::Delimiter::Tab),
+            OutputFormat::Html => html::write(self, out),
+            OutputFormat::Fdupes {
+                same_line,
+                show_size,
+            } => fdupes::write(self, out, same_line, show_size),
+        }
+    }
+
+    // Historical console output: one path per line, blank line between groups
+    fn write_text<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+        if self.groups.is_empty() {
+            writeln!(
+                out,
+                "Among {} files, no dupes were found.",
+                self.summary.files_scanned
+            )?;
+            if !self.overlaps.is_empty() {
+                writeln!(out)?;
+            }
+            return self.write_overlaps(out);
+        }
+
+        writeln!(
+            out,
+            "Found {} identical files:",
+            self.summary.duplicate_files
+        )?;
+        for group in &self.groups {
+            for file in &group.files {
+                match group.relations.get(&file.file_path) {
+                    Some(relation) => writeln!(out, "{} ({relation})", file.file_path)?,
+                    None => writeln!(out, "{}", file.file_path)?,
+                }
+            }
+            writeln!(out)?; // Add an empty line between dupe groups
+        }
+        self.write_overlaps(out)?;
+        self.write_albums(out)?;
+        self.write_root_matrix(out)
+    }
+
+    fn write_overlaps<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+        if self.overlaps.is_empty() {
+            return Ok(());
+        }
+        writeln!(out, "Tracks inside longer files:")?;
+        for overlap in &self.overlaps {
+            writeln!(out, "  {}", overlap.describe())?;
+        }
+        writeln!(out)
+    }
+
+    fn write_albums<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+        if self.albums.is_empty() {
+            return Ok(());
+        }
+        writeln!(out, "Duplicate albums:")?;
+        for album in &self.albums {
+            writeln!(out, "  {}", album.describe())?;
+        }
+        writeln!(out)
+    }
+
+    // Lower triangle of shared tracks per pair of input roots, numbered after the legend
+    fn write_root_matrix<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+        if self.roots.len() < 2 {
+            return Ok(());
+        }
+        writeln!(out, "Shared tracks between inputs:")?;
+        for (idx, root) in self.roots.iter().enumerate() {
+            writeln!(out, "  [{}] {root}", idx + 1)?;
+        }
+
+        let cell = |a: usize, b: usize| {
+            let pair = self
+                .root_pairs
+                .iter()
+                .find(|p| p.a == self.roots[a] && p.b == self.roots[b])
+                .expect("every pair is listed");
+            if pair.tracks == 0 {
+                "-".to_string()
+            } else {
+                format!("{} ({})", pair.tracks, format_size_bytes(pair.bytes))
+            }
+        };
+        let cells: Vec<Vec<String>> = (1..self.roots.len())
+            .map(|row| (0..row).map(|col| cell(col, row)).collect())
+            .collect();
+        let label_width = format!("[{}]", self.roots.len()).len();
+        let width = cells
+            .iter()
+            .flatten()
+            .map(String::len)
+            .max()
+            .unwrap_or(0)
+            .max(label_width);
+
+        let mut header = format!("  {:label_width$}", "");
+        for col in 0..self.roots.len() - 1 {
+            header.push_str(&format!("  {:<width$}", format!("[{}]", col + 1)));
+        }
+        writeln!(out, "{}", header.trim_end())?;
+        for (row, row_cells) in cells.iter().enumerate() {
+            let mut line = format!("  {:label_width$}", format!("[{}]", row + 2));
+            for cell in row_cells {
+                line.push_str(&format!("  {cell:<width$}"));
+            }
+            writeln!(out, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+// How a copy differs from the kept file: channel layout, then level, then a lossless verdict
+// the kept file does not share
+fn relation(group: &DuplicateGroup, file: &AudioFile, kept: &AudioFile) -> Option<String> {
+    let mut labels: Vec<String> = channel_relation(file, kept).into_iter().collect();
+    let offsets = &group.gain_offsets;
+    if let (Some(ours), Some(theirs)) = (offsets.get(&file.file_path), offsets.get(&kept.file_path))
+        && (ours - theirs).abs() >= 0.005
+    {
+        labels.push(format!("gain {:+.2} dB", ours - theirs));
+    }
+    let verdict = |file: &AudioFile| file.lossless.map(|check| check.verdict);
+    if let Some(check) = file.lossless
+        && verdict(file) != verdict(kept)
+        && let Some(label) = check.describe(file.bit_depth)
+    {
+        labels.push(label);
+    }
+    (!labels.is_empty()).then(|| labels.join(", "))
+}
+
+// Roots are only known for files found by a scan (or assigned by `report -i`)
+fn root_matrix(groups: &[ReportGroup]) -> (Vec<String>, Vec<RootPair>) {
+    let roots: BTreeSet<&str> = groups
+        .iter()
+        .flat_map(|g| &g.files)
+        .map(|f| f.root.as_str())
+        .filter(|root| !root.is_empty())
+        .collect();
+    if roots.len() < 2 {
+        return (Vec::new(), Vec::new());
+    }
+
+    let mut pairs: BTreeMap<(&str, &str), (usize, u64)> = BTreeMap::new();
+    for group in groups {
+        let mut smallest: BTreeMap<&str, u64> = BTreeMap::new();
+        for file in group.files.iter().filter(|f| !f.root.is_empty()) {
+            smallest
+                .entry(file.root.as_str())
+                .and_modify(|size| *size = (*size).min(file.file_size))
+                .or_insert(file.file_size);
+        }
+        let sides: Vec<(&str, u64)> = smallest.into_iter().collect();
+        for (idx, (a, a_size)) in sides.iter().enumerate() {
+            for (b, b_size) in &sides[idx + 1..] {
+                let cell = pairs.entry((a, b)).or_default();
+                cell.0 += 1;
+                cell.1 += (*a_size).min(*b_size);
+            }
+        }
+    }
+
+    let roots: Vec<&str> = roots.into_iter().collect();
+    let mut root_pairs = Vec::new();
+    for (idx, a) in roots.iter().enumerate() {
+        for b in &roots[idx + 1..] {
+            let (tracks, bytes) = pairs.get(&(*a, *b)).copied().unwrap_or_default();
+            root_pairs.push(RootPair {
+                a: a.to_string(),
+                b: b.to_string(),
+                tracks,
+                bytes,
+            });
+        }
+    }
+    (roots.into_iter().map(String::from).collect(), root_pairs)
+}
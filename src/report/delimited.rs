@@ -0,0 +1,102 @@
+use super::Report;
+use crate::format_unix_time;
+use std::io::Write;
+
+const HEADER: [&str; 13] = [
+    "group_id",
+    "role",
+    "path",
+    "format",
+    "sample_rate",
+    "bit_depth",
+    "channels",
+    "duration_secs",
+    "peak_level",
+    "rms_db_level",
+    "file_size",
+    "modified_utc",
+    "modified_secs",
+];
+
+#[derive(Clone, Copy)]
+pub(crate) enum Delimiter {
+    Comma,
+    Tab,
+}
+
+impl Delimiter {
+    pub(crate) fn separator(self) -> &'static str {
+        match self {
+            Delimiter::Comma => ",",
+            Delimiter::Tab => "\t",
+        }
+    }
+
+    // CRLF is what spreadsheet tools expect from CSV; TSV stays on plain newlines
+    pub(crate) fn line_end(self) -> &'static str {
+        match self {
+            Delimiter::Comma => "\r\n",
+            Delimiter::Tab => "\n",
+        }
+    }
+
+    // CSV: RFC 4180 quoting. TSV cannot quote, so tabs/newlines/backslashes are backslash-escaped.
+    pub(crate) fn escape(self, field: &str) -> String {
+        match self {
+            Delimiter::Comma => {
+                if field.contains([',', '"', '\n', '\r']) {
+                    format!("\"{}\"", field.replace('"', "\"\""))
+                } else {
+                    field.to_string()
+                }
+            }
+            Delimiter::Tab => {
+                let mut escaped = String::with_capacity(field.len());
+                for ch in field.chars() {
+                    match ch {
+                        '\\' => escaped.push_str("\\\\"),
+                        '\t' => escaped.push_str("\\t"),
+                        '\n' => escaped.push_str("\\n"),
+                        '\r' => escaped.push_str("\\r"),
+                        _ => escaped.push(ch),
+                    }
+                }
+                escaped
+            }
+        }
+    }
+}
+
+// One row per file of every duplicate group
+pub(super) fn write<W: Write>(
+    report: &Report,
+    out: &mut W,
+    delimiter: Delimiter,
+) -> std::io::Result<()> {
+    let sep = delimiter.separator();
+    write!(out, "{}{}", HEADER.join(sep), delimiter.line_end())?;
+
+    for group in &report.groups {
+        for file in &group.files {
+            let role = group.role(file);
+            let fields = [
+                group.id.to_string(),
+                role.to_string(),
+                file.file_path.clone(),
+                file.format(),
+                file.sample_rate.to_string(),
+                file.bit_depth.to_string(),
+                file.channels.to_string(),
+                format!("{:.3}", file.duration_secs()),
+                file.peak_level.to_string(),
+                file.rms_db_level.to_string(),
+                file.file_size.to_string(),
+                format_unix_time(file.modified_secs),
+                file.modified_secs.to_string(),
+            ];
+            let row: Vec<String> = fields.iter().map(|f| delimiter.escape(f)).collect();
+            write!(out, "{}{}", row.join(sep), delimiter.line_end())?;
+        }
+    }
+    Ok(())
+}
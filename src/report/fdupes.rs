@@ -0,0 +1,46 @@
+use super::Report;
+use std::io::Write;
+
+// fdupes -1 separates names with spaces, so spaces and backslashes inside names are escaped
+fn escape_same_line(path: &str) -> String {
+    path.replace('\\', "\\\\").replace(' ', "\\ ")
+}
+
+// fdupes/jdupes layout: one path per line and a blank line after each group, or with -1 one
+// group per line and no blank lines. No banner, and nothing at all when there are no dupes.
+pub(super) fn write<W: Write>(
+    report: &Report,
+    out: &mut W,
+    same_line: bool,
+    show_size: bool,
+) -> std::io::Result<()> {
+    for group in &report.groups {
+        if show_size {
+            // Audio-identical files may differ in size (tags, padding) while fdupes shows one size
+            // per group: keep its header when sizes agree, say whose size it is otherwise
+            let size = group.files[0].file_size;
+            let plural = if size == 1 { "" } else { "s" };
+            if group.files.iter().all(|f| f.file_size == size) {
+                writeln!(out, "{size} byte{plural} each:")?;
+            } else {
+                writeln!(out, "{size} byte{plural} (first file, sizes differ):")?;
+            }
+        }
+
+        if same_line {
+            let line: Vec<String> = group
+                .files
+                .iter()
+                .map(|f| escape_same_line(&f.file_path))
+                .collect();
+            // Every name is followed by a space, the line included
+            writeln!(out, "{} ", line.join(" "))?;
+        } else {
+            for file in &group.files {
+                writeln!(out, "{}", file.file_path)?;
+            }
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
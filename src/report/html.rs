@@ -0,0 +1,276 @@
+use super::{Report, ReportGroup};
+use crate::{format_size_bytes, format_unix_time};
+use std::collections::BTreeSet;
+use std::io::Write;
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(ch),
+        }
+    }
+    out
+}
+
+const STYLE: &str = r#"
+body { font-family: system-ui, sans-serif; margin: 1.5em; color: #222; }
+h1 { font-size: 1.4em; }
+table { border-collapse: collapse; width: 100%; font-size: 0.9em; }
+th, td { border-bottom: 1px solid #ddd; padding: 0.3em 0.5em; text-align: left; vertical-align: top; }
+th.sort { cursor: pointer; user-select: none; }
+th.sort:hover { text-decoration: underline; }
+tr.group td { background: #eef2f7; font-weight: 600; }
+tr.kept td:first-child { color: #1a7f37; }
+tr.duplicate td:first-child { color: #b42318; }
+tr.reference td:first-child { color: #1f6feb; }
+td.num { text-align: right; white-space: nowrap; }
+td.path { word-break: break-all; }
+mark { background: #fde68a; }
+.summary td { border: none; padding: 0.1em 1em 0.1em 0; }
+.controls { margin: 1em 0; }
+.controls input[type=search] { width: 30em; padding: 0.3em; }
+.meta { color: #666; font-size: 0.85em; }
+"#;
+
+const SCRIPT: &str = r#"
+(function () {
+  var table = document.getElementById('groups');
+  var filter = document.getElementById('filter');
+  var diffOnly = document.getElementById('diff-only');
+  var bodies = function () { return Array.prototype.slice.call(table.tBodies); };
+
+  function apply() {
+    var needle = filter.value.toLowerCase();
+    var shown = 0;
+    bodies().forEach(function (body) {
+      var match = body.textContent.toLowerCase().indexOf(needle) !== -1;
+      if (diffOnly.checked && body.dataset.tagdiff !== '1') match = false;
+      body.style.display = match ? '' : 'none';
+      if (match) shown++;
+    });
+    document.getElementById('shown').textContent = shown;
+  }
+
+  var current = { key: 'id', dir: 1 };
+  function sortBy(key) {
+    current.dir = current.key === key ? -current.dir : 1;
+    current.key = key;
+    var numeric = key !== 'title';
+    bodies()
+      .sort(function (a, b) {
+        var x = a.dataset[key], y = b.dataset[key];
+        if (numeric) { x = parseFloat(x); y = parseFloat(y); }
+        return (x < y ? -1 : x > y ? 1 : 0) * current.dir;
+      })
+      .forEach(function (body) { table.appendChild(body); });
+  }
+
+  filter.addEventListener('input', apply);
+  diffOnly.addEventListener('change', apply);
+  Array.prototype.forEach.call(document.querySelectorAll('th.sort'), function (th) {
+    th.addEventListener('click', function () { sortBy(th.dataset.key); });
+  });
+  apply();
+})();
+"#;
+
+// Tags rendered as "KEY=value" with anything differing from the kept file highlighted
+fn tag_cell(group: &ReportGroup, file_idx: usize) -> (String, bool) {
+    let kept = group
+        .files
+        .iter()
+        .find(|f| f.file_path == group.kept)
+        .unwrap_or(&group.files[0]);
+    let file = &group.files[file_idx];
+    let keys: BTreeSet<&String> = file.tags.keys().chain(kept.tags.keys()).collect();
+
+    let mut differs = false;
+    let parts: Vec<String> = keys
+        .into_iter()
+        .map(|key| {
+            let value = file.tags.get(key);
+            let text = format!(
+                "{}={}",
+                escape(key),
+                escape(value.map(String::as_str).unwrap_or("∅"))
+            );
+            if value == kept.tags.get(key) {
+                text
+            } else {
+                differs = true;
+                format!("<mark>{text}</mark>")
+            }
+        })
+        .collect();
+    (parts.join("<br>"), differs)
+}
+
+fn group_title(group: &ReportGroup) -> String {
+    group
+        .files
+        .iter()
+        .map(|f| f.tag_label())
+        .find(|label| !label.is_empty())
+        .unwrap_or_else(|| {
+            std::path::Path::new(&group.kept)
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default()
+        })
+}
+
+// Single static page: summary, errors and a sortable/filterable table of groups (one tbody per group)
+pub(super) fn write<W: Write>(report: &Report, out: &mut W) -> std::io::Result<()> {
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, "<html lang=\"en\"><head><meta charset=\"utf-8\">")?;
+    writeln!(out, "<title>fadupes report</title>")?;
+    writeln!(out, "<style>{STYLE}</style></head><body>")?;
+    writeln!(out, "<h1>fadupes duplicate report</h1>")?;
+
+    writeln!(out, "<p class=\"meta\">")?;
+    for line in report.run.header_lines() {
+        writeln!(out, "{}<br>", escape(&line))?;
+    }
+    writeln!(out, "</p>")?;
+
+    let summary = &report.summary;
+    writeln!(out, "<table class=\"summary\">")?;
+    for (label, value) in [
+        ("Files scanned", summary.files_scanned.to_string()),
+        ("Duplicate groups", summary.groups.to_string()),
+        ("Files in groups", summary.duplicate_files.to_string()),
+        (
+            "Reclaimable space",
+            format_size_bytes(summary.reclaimable_bytes),
+        ),
+        ("Errors", report.errors.len().to_string()),
+    ] {
+        writeln!(
+            out,
+            "<tr><td>{label}</td><td><b>{}</b></td></tr>",
+            escape(&value)
+        )?;
+    }
+    writeln!(out, "</table>")?;
+
+    if !report.errors.is_empty() {
+        writeln!(out, "<h2>Errors</h2><table>")?;
+        writeln!(
+            out,
+            "<thead><tr><th>Path</th><th>Error</th></tr></thead><tbody>"
+        )?;
+        for error in &report.errors {
+            writeln!(
+                out,
+                "<tr><td class=\"path\">{}</td><td>{}</td></tr>",
+                escape(&error.path),
+                escape(&error.message)
+            )?;
+        }
+        writeln!(out, "</tbody></table>")?;
+    }
+
+    if !report.albums.is_empty() {
+        writeln!(out, "<h2>Duplicate albums</h2><table>")?;
+        writeln!(
+            out,
+            "<thead><tr><th>Directory</th><th>Copy of</th><th>Tracks</th><th>Missing</th></tr></thead><tbody>"
+        )?;
+        for album in &report.albums {
+            writeln!(
+                out,
+                "<tr><td class=\"path\">{}/</td><td class=\"path\">{}/</td><td>{}/{}</td><td>{}</td></tr>",
+                escape(&album.a),
+                escape(&album.b),
+                album.a_tracks,
+                album.b_tracks,
+                album.missing
+            )?;
+        }
+        writeln!(out, "</tbody></table>")?;
+    }
+
+    writeln!(out, "<h2>Duplicate groups</h2>")?;
+    writeln!(
+        out,
+        "<div class=\"controls\"><input type=\"search\" id=\"filter\" placeholder=\"Filter by path, tag, format…\"> \
+         <label><input type=\"checkbox\" id=\"diff-only\"> only groups with tag differences</label> \
+         <span class=\"meta\"><span id=\"shown\"></span> groups shown</span></div>"
+    )?;
+    writeln!(out, "<table id=\"groups\"><thead><tr>")?;
+    for (key, label) in [
+        ("id", "Group / role"),
+        ("title", "Title / path"),
+        ("", "Format"),
+        ("", "Rate"),
+        ("", "Depth"),
+        ("", "Ch"),
+        ("duration", "Duration"),
+        ("files", "Files / size"),
+        ("reclaimable", "Reclaimable / modified"),
+        ("", "Tags"),
+    ] {
+        if key.is_empty() {
+            write!(out, "<th>{label}</th>")?;
+        } else {
+            write!(out, "<th class=\"sort\" data-key=\"{key}\">{label}</th>")?;
+        }
+    }
+    writeln!(out, "</tr></thead>")?;
+
+    for group in &report.groups {
+        let cells: Vec<(String, bool)> = (0..group.files.len())
+            .map(|idx| tag_cell(group, idx))
+            .collect();
+        let tag_diff = cells.iter().any(|(_, differs)| *differs);
+        let title = group_title(group);
+        let first = &group.files[0];
+
+        writeln!(
+            out,
+            "<tbody data-id=\"{}\" data-title=\"{}\" data-files=\"{}\" data-reclaimable=\"{}\" data-duration=\"{:.3}\" data-tagdiff=\"{}\">",
+            group.id,
+            escape(&title.to_lowercase()),
+            group.files.len(),
+            group.reclaimable_bytes,
+            first.duration_secs(),
+            u8::from(tag_diff)
+        )?;
+        writeln!(
+            out,
+            "<tr class=\"group\"><td>#{}</td><td>{}</td><td colspan=\"4\"></td><td class=\"num\">{:.1}s</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td>{}</td></tr>",
+            group.id,
+            escape(&title),
+            first.duration_secs(),
+            group.files.len(),
+            format_size_bytes(group.reclaimable_bytes),
+            if tag_diff { "tags differ" } else { "" }
+        )?;
+
+        for (file, (tags, _)) in group.files.iter().zip(cells) {
+            let role = group.role(file);
+            writeln!(
+                out,
+                "<tr class=\"{role}\"><td>{role}</td><td class=\"path\">{}</td><td>{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td></td><td class=\"num\">{}</td><td class=\"num\">{}</td><td>{tags}</td></tr>",
+                escape(&file.file_path),
+                escape(&file.format()),
+                file.sample_rate,
+                file.bit_depth,
+                file.channels,
+                format_size_bytes(file.file_size),
+                format_unix_time(file.modified_secs),
+            )?;
+        }
+        writeln!(out, "</tbody>")?;
+    }
+    writeln!(out, "</table>")?;
+
+    writeln!(out, "<script>{SCRIPT}</script>")?;
+    writeln!(out, "</body></html>")
+}
@@ -0,0 +1,114 @@
+use crate::actions::now_secs;
+use crate::format_unix_time;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Describes one fadupes invocation; written at the top of every report, script and log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunInfo {
+    pub version: String,
+    pub started_secs: u64,
+    pub inputs: Vec<String>,
+    pub options: BTreeMap<String, String>,
+}
+
+impl RunInfo {
+    pub fn new(inputs: Vec<String>, options: BTreeMap<String, String>) -> Self {
+        RunInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            started_secs: now_secs(),
+            inputs,
+            options,
+        }
+    }
+
+    // Plain text lines (no comment prefix) for headers of text based outputs
+    pub fn header_lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("fadupes {}", self.version),
+            format!("started: {} UTC", format_unix_time(self.started_secs)),
+        ];
+        for input in &self.inputs {
+            lines.push(format!("input: {input}"));
+        }
+        let options: Vec<String> = self
+            .options
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect();
+        if !options.is_empty() {
+            lines.push(format!("options: {}", options.join(" ")));
+        }
+        lines
+    }
+}
+
+// Text log shared by all scanning threads. Opened on first write so an empty error log is never
+// created, and every run starts with a "## " header so appended runs stay distinguishable.
+// Write failures are reported once on stderr instead of aborting the scan.
+#[derive(Debug)]
+pub struct RunLog {
+    path: PathBuf,
+    truncate: bool,
+    header: Vec<String>,
+    file: Mutex<Option<File>>,
+    failed: AtomicBool,
+}
+
+impl RunLog {
+    pub fn new(path: PathBuf, truncate: bool, run: &RunInfo) -> Self {
+        RunLog {
+            path,
+            truncate,
+            header: run.header_lines(),
+            file: Mutex::new(None),
+            failed: AtomicBool::new(false),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn write_line(&self, line: &str) {
+        if self.failed.load(Ordering::Relaxed) {
+            return;
+        }
+        let Ok(mut file) = self.file.lock() else {
+            return;
+        };
+        if let Err(err) = self.write_locked(&mut file, line) {
+            // Keep going without the log, but say so exactly once
+            if !self.failed.swap(true, Ordering::Relaxed) {
+                eprintln!(
+                    "Warning: cannot write log file {}: {err}. Continuing without it.",
+                    self.path.display()
+                );
+            }
+        }
+    }
+
+    fn write_locked(&self, file: &mut Option<File>, line: &str) -> std::io::Result<()> {
+        if file.is_none() {
+            let mut opened = std::fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(!self.truncate)
+                .truncate(self.truncate)
+                .open(&self.path)?;
+            for header in &self.header {
+                writeln!(opened, "## {header}")?;
+            }
+            *file = Some(opened);
+        }
+        match file.as_mut() {
+            Some(file) => writeln!(file, "{line}"),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use crate::actions::{ActionPlan, Decision, DupeAction};
+use crate::format_size_bytes;
+use crate::run::RunInfo;
+use std::io::Write;
+use std::path::Path;
+
+// Quote for POSIX sh: everything inside single quotes is literal except the quote itself
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+// Comments must stay on one line, otherwise a crafted file name could smuggle in a command
+fn comment_safe(s: &str) -> String {
+    s.chars()
+        .map(|c| if c.is_control() { '?' } else { c })
+        .collect()
+}
+
+const PRELUDE: &str = r#"set -u
+
+# Print "size mtime" with GNU stat, falling back to BSD stat
+file_stat() {
+    stat -c '%s %Y' -- "$1" 2>/dev/null || stat -f '%z %m' -- "$1" 2>/dev/null
+}
+
+# Refuse to touch a file whose size or mtime changed since the scan
+check() {
+    if [ "$(file_stat "$1")" != "$2 $3" ]; then
+        echo "skipping, changed since scan: $1" >&2
+        return 1
+    fi
+}
+"#;
+
+// Write a reviewable shell script acting on every duplicate of the plan. Kept files are only
+// checked: a group whose kept file changed since the scan is skipped whole.
+pub fn write_script<W: Write>(
+    out: &mut W,
+    plan: &ActionPlan,
+    action: &DupeAction,
+    run: &RunInfo,
+) -> std::io::Result<()> {
+    writeln!(out, "#!/bin/sh")?;
+    for line in run.header_lines() {
+        writeln!(out, "# {}", comment_safe(&line))?;
+    }
+    writeln!(out, "# action: {}", comment_safe(&action.describe()))?;
+    writeln!(
+        out,
+        "# {} files to process, {} reclaimable",
+        plan.pending_deletes(),
+        format_size_bytes(plan.reclaimable_bytes())
+    )?;
+    writeln!(out, "#")?;
+    writeln!(out, "# Review and edit before running: sh <this script>")?;
+    writeln!(out)?;
+    write!(out, "{PRELUDE}")?;
+
+    for (idx, group) in plan.groups.iter().enumerate() {
+        // Groups with nothing to delete have nothing to guard either
+        if !group.files.iter().any(|p| p.decision == Decision::Delete) {
+            continue;
+        }
+        let Some(kept) = group.kept() else {
+            continue;
+        };
+        writeln!(out)?;
+        writeln!(
+            out,
+            "# Group {} ({} files, {} reclaimable)",
+            idx + 1,
+            group.files.len(),
+            format_size_bytes(group.reclaimable_bytes())
+        )?;
+        writeln!(
+            out,
+            "# keep: {}",
+            comment_safe(&shell_quote(&kept.file_path))
+        )?;
+        writeln!(
+            out,
+            "if check {} {} {}; then",
+            shell_quote(&kept.file_path),
+            kept.file_size,
+            kept.modified_secs
+        )?;
+
+        for planned in group
+            .files
+            .iter()
+            .filter(|p| p.decision == Decision::Delete)
+        {
+            let file = &planned.file;
+            let path = shell_quote(&file.file_path);
+            let command = match action {
+                DupeAction::Remove => format!("rm -f -- {path}"),
+                DupeAction::Hardlink => {
+                    format!("ln -f -- {} {path}", shell_quote(&kept.file_path))
+                }
+                DupeAction::Symlink => {
+                    format!("ln -sf -- {} {path}", shell_quote(&kept.file_path))
+                }
+                DupeAction::Move(dir) => {
+                    let target = move_target(dir, &file.file_path);
+                    let parent = Path::new(&target)
+                        .parent()
+                        .map(|p| p.to_string_lossy().to_string())
+                        .unwrap_or_default();
+                    format!(
+                        "mkdir -p -- {} && mv -- {path} {}",
+                        shell_quote(&parent),
+                        shell_quote(&target)
+                    )
+                }
+            };
+            writeln!(
+                out,
+                "    check {path} {} {} && {command}",
+                file.file_size, file.modified_secs
+            )?;
+        }
+        writeln!(out, "fi")?;
+    }
+
+    Ok(())
+}
+
+// Moved files keep their absolute layout under the target dir so nothing collides
+pub fn move_target(dir: &Path, file_path: &str) -> String {
+    let relative = file_path.trim_start_matches('/');
+    dir.join(relative).to_string_lossy().to_string()
+}
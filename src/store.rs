@@ -0,0 +1,135 @@
+use crate::CachedEntry;
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+
+mod binary;
+mod lock;
+mod sqlite;
+
+pub use binary::BinaryStore;
+pub use lock::StateLock;
+pub use sqlite::SqliteStore;
+
+// On-disk layout of the resume state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateFormat {
+    // Versioned append-only log, compacted from time to time
+    Binary,
+    // Whole map rewritten on every checkpoint; also the import/export format
+    Json,
+    // Embedded database with incremental upserts and scan history
+    Sqlite,
+}
+
+impl StateFormat {
+    // .json keeps the historical layout, .db/.sqlite/.sqlite3 are databases, anything else is binary
+    pub fn from_path(path: &Path) -> Self {
+        let ext = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        match ext.as_str() {
+            "json" => StateFormat::Json,
+            "db" | "sqlite" | "sqlite3" => StateFormat::Sqlite,
+            _ => StateFormat::Binary,
+        }
+    }
+}
+
+impl std::str::FromStr for StateFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "binary" => Ok(StateFormat::Binary),
+            "json" => Ok(StateFormat::Json),
+            "sqlite" => Ok(StateFormat::Sqlite),
+            other => Err(format!(
+                "unknown state format \"{other}\" (use binary, json or sqlite)"
+            )),
+        }
+    }
+}
+
+// Where ResumeCache persists its map
+#[derive(Debug)]
+pub(crate) enum StateBackend {
+    Binary(BinaryStore),
+    Json,
+    Sqlite(SqliteStore),
+}
+
+// Read-only load of any state file, for imports and merges
+pub fn read_state(path: &Path) -> std::io::Result<HashMap<String, CachedEntry>> {
+    // Opening a database creates it, so check first
+    if !path.exists() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "state file not found",
+        ));
+    }
+    match StateFormat::from_path(path) {
+        StateFormat::Binary => binary::read(path),
+        StateFormat::Json => read_json(path),
+        StateFormat::Sqlite => SqliteStore::open(path)?.load_all(),
+    }
+}
+
+// Length and mtime of the state file, to notice when another process wrote it since we last did
+pub(crate) fn disk_stamp(path: &Path) -> Option<(u64, std::time::SystemTime)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+pub(crate) fn read_json(path: &Path) -> std::io::Result<HashMap<String, CachedEntry>> {
+    let file = File::open(path)?;
+    Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+}
+
+// Atomic-ish save: write to a temp file then rename, so we don't leave a half-written JSON behind
+pub(crate) fn write_json(path: &Path, map: &HashMap<String, CachedEntry>) -> std::io::Result<()> {
+    if let Some(parent) = path.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let tmp_path = path.with_extension("tmp");
+    let mut out = std::io::BufWriter::new(File::create(&tmp_path)?);
+    serde_json::to_writer(&mut out, map)?;
+    let file = out.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?; // ensure bytes hit disk before rename
+    std::fs::rename(tmp_path, path)
+}
+
+const CONTENT_KEY_BLOCK: u64 = 64 * 1024;
+
+// Cheap identity of a file's bytes: FNV-1a over the size and the first and last 64 KiB.
+// Stable across runs and platforms (unlike std's hasher), so it can be persisted.
+pub fn content_key(path: &Path, size: u64) -> std::io::Result<u64> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut feed = |bytes: &[u8]| {
+        for &byte in bytes {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    };
+    feed(&size.to_le_bytes());
+
+    let mut file = File::open(path)?;
+    let mut buf = vec![0; CONTENT_KEY_BLOCK.min(size) as usize];
+    file.read_exact(&mut buf)?;
+    feed(&buf);
+
+    let tail = CONTENT_KEY_BLOCK.min(size - buf.len() as u64);
+    if tail > 0 {
+        file.seek(SeekFrom::Start(size - tail))?;
+        buf.resize(tail as usize, 0);
+        file.read_exact(&mut buf)?;
+        feed(&buf);
+    }
+    Ok(hash)
+}
@@ -0,0 +1,435 @@
+use crate::lossless::{LosslessCheck, Verdict};
+use crate::{AudioFile, CachedEntry, ChannelStats, FileIdentity};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// File layout: MAGIC, u32 schema version, then records of (u8 kind, u32 length, body).
+// All integers are little endian. Later records for a path replace earlier ones.
+const MAGIC: &[u8; 8] = b"FADUPES\0";
+// Bumped whenever a record body changes; older versions must stay readable
+const SCHEMA_VERSION: u32 = 1;
+const HEADER_LEN: usize = MAGIC.len() + 4;
+
+const RECORD_PUT: u8 = 1;
+const RECORD_REMOVE: u8 = 2;
+
+// Rewrite the log once it holds this many times more records than live entries
+const COMPACT_RATIO: usize = 2;
+const COMPACT_MIN_RECORDS: usize = 1024;
+
+fn invalid(message: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, message.into())
+}
+
+fn header() -> Vec<u8> {
+    let mut buf = MAGIC.to_vec();
+    buf.extend(SCHEMA_VERSION.to_le_bytes());
+    buf
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    buf.extend((s.len() as u32).to_le_bytes());
+    buf.extend(s.as_bytes());
+}
+
+fn encode_put(buf: &mut Vec<u8>, entry: &CachedEntry) {
+    let file = &entry.audio_file;
+    let mut body = Vec::with_capacity(128);
+    put_str(&mut body, &file.file_path);
+    put_str(&mut body, &file.file_name);
+    body.extend(entry.file_size.to_le_bytes());
+    body.extend(entry.modified_secs.to_le_bytes());
+    body.extend(file.total_samples.to_le_bytes());
+    body.extend(file.sample_rate.to_le_bytes());
+    body.extend(file.bit_depth.to_le_bytes());
+    body.extend(file.channels.to_le_bytes());
+    body.extend(file.peak_level.to_bits().to_le_bytes());
+    body.extend(file.rms_db_level.to_bits().to_le_bytes());
+    body.extend((file.tags.len() as u32).to_le_bytes());
+    for (key, value) in &file.tags {
+        put_str(&mut body, key);
+        put_str(&mut body, value);
+    }
+    match entry.content_key {
+        Some(key) => {
+            body.push(1);
+            body.extend(key.to_le_bytes());
+        }
+        None => body.push(0),
+    }
+    match entry.identity {
+        Some(identity) => {
+            body.push(1);
+            body.extend(identity.modified_nanos.to_le_bytes());
+            body.extend(identity.changed_secs.to_le_bytes());
+            body.extend(identity.changed_nanos.to_le_bytes());
+            body.extend(identity.inode.to_le_bytes());
+            body.extend(identity.device.to_le_bytes());
+        }
+        None => body.push(0),
+    }
+    body.extend((file.channel_stats.len() as u32).to_le_bytes());
+    for stats in &file.channel_stats {
+        body.extend(stats.peak_level.to_bits().to_le_bytes());
+        body.extend(stats.rms_db_level.to_bits().to_le_bytes());
+        body.extend(stats.hash.to_le_bytes());
+        body.extend(stats.abs_hash.to_le_bytes());
+    }
+    match file.lossless {
+        Some(check) => {
+            body.push(1);
+            put_str(&mut body, check.verdict.name());
+            body.extend(check.cutoff_hz.to_le_bytes());
+            body.extend(check.padded_bits.to_le_bytes());
+        }
+        None => body.push(0),
+    }
+    body.extend(file.effective_bit_depth.to_le_bytes());
+    body.extend(file.effective_sample_rate.to_le_bytes());
+
+    buf.push(RECORD_PUT);
+    buf.extend((body.len() as u32).to_le_bytes());
+    buf.extend(body);
+}
+
+fn encode_remove(buf: &mut Vec<u8>, path: &str) {
+    let mut body = Vec::with_capacity(path.len() + 4);
+    put_str(&mut body, path);
+    buf.push(RECORD_REMOVE);
+    buf.extend((body.len() as u32).to_le_bytes());
+    buf.extend(body);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> std::io::Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| invalid("record ends early"))?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn array<const N: usize>(&mut self) -> std::io::Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().expect("length checked by take"))
+    }
+
+    fn u32(&mut self) -> std::io::Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> std::io::Result<u64> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn string(&mut self) -> std::io::Result<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| invalid("string is not UTF-8"))
+    }
+}
+
+fn decode_put(body: &[u8]) -> std::io::Result<CachedEntry> {
+    let mut r = Reader {
+        bytes: body,
+        pos: 0,
+    };
+    let file_path = r.string()?;
+    let file_name = r.string()?;
+    let file_size = r.u64()?;
+    let modified_secs = r.u64()?;
+    let mut audio_file = AudioFile {
+        file_path,
+        file_name,
+        total_samples: r.u64()?,
+        sample_rate: r.u32()?,
+        bit_depth: r.u32()?,
+        channels: r.u32()?,
+        peak_level: f32::from_bits(r.u32()?),
+        rms_db_level: f64::from_bits(r.u64()?),
+        file_size,
+        modified_secs,
+        tags: Default::default(),
+        channel_stats: Vec::new(),
+        lossless: None,
+        effective_bit_depth: 0,
+        effective_sample_rate: 0,
+        reference: false,
+        root: String::new(),
+    };
+    for _ in 0..r.u32()? {
+        let key = r.string()?;
+        let value = r.string()?;
+        audio_file.tags.insert(key, value);
+    }
+    let content_key = if r.array::<1>()?[0] == 1 {
+        Some(r.u64()?)
+    } else {
+        None
+    };
+    let identity = if r.array::<1>()?[0] == 1 {
+        Some(FileIdentity {
+            modified_nanos: r.u32()?,
+            changed_secs: r.u64()? as i64,
+            changed_nanos: r.u32()?,
+            inode: r.u64()?,
+            device: r.u64()?,
+        })
+    } else {
+        None
+    };
+    for _ in 0..r.u32()? {
+        audio_file.channel_stats.push(ChannelStats {
+            peak_level: f32::from_bits(r.u32()?),
+            rms_db_level: f64::from_bits(r.u64()?),
+            hash: r.u64()?,
+            abs_hash: r.u64()?,
+        });
+    }
+    if r.array::<1>()?[0] == 1 {
+        let verdict = r.string()?;
+        audio_file.lossless = Some(LosslessCheck {
+            verdict: Verdict::from_name(&verdict)
+                .ok_or_else(|| invalid(format!("unknown lossless verdict {verdict:?}")))?,
+            cutoff_hz: r.u32()?,
+            padded_bits: r.u32()?,
+        });
+    }
+    audio_file.effective_bit_depth = r.u32()?;
+    audio_file.effective_sample_rate = r.u32()?;
+    Ok(CachedEntry {
+        audio_file,
+        file_size,
+        modified_secs,
+        content_key,
+        identity,
+    })
+}
+
+struct DecodedLog {
+    map: HashMap<String, CachedEntry>,
+    version: u32,
+    records: usize,
+    // Length of the readable prefix; a crash during an append can leave a partial record behind
+    valid_len: usize,
+}
+
+fn decode_log(bytes: &[u8]) -> std::io::Result<DecodedLog> {
+    let mut r = Reader { bytes, pos: 0 };
+    if r.take(MAGIC.len())? != MAGIC {
+        return Err(invalid("not a fadupes state file"));
+    }
+    let version = r.u32()?;
+    if version > SCHEMA_VERSION {
+        // Unsupported, not a broken log: the caller must not replace it
+        return Err(std::io::Error::new(
+            ErrorKind::Unsupported,
+            format!(
+                "state schema version {version} is newer than this fadupes understands ({SCHEMA_VERSION})"
+            ),
+        ));
+    }
+
+    let mut log = DecodedLog {
+        map: HashMap::new(),
+        version,
+        records: 0,
+        valid_len: bytes.len(),
+    };
+    while r.pos < bytes.len() {
+        let start = r.pos;
+        let Ok(kind) = r.array::<1>().map(|b| b[0]) else {
+            log.valid_len = start;
+            break;
+        };
+        let Ok(body) = r.u32().and_then(|len| r.take(len as usize)) else {
+            log.valid_len = start;
+            break;
+        };
+        // Unknown kinds are skipped so older binaries can read logs with newer optional records
+        match kind {
+            RECORD_PUT => {
+                let entry = decode_put(body)?;
+                log.map.insert(entry.audio_file.file_path.clone(), entry);
+            }
+            RECORD_REMOVE => {
+                let path = Reader {
+                    bytes: body,
+                    pos: 0,
+                }
+                .string()?;
+                log.map.remove(&path);
+            }
+            _ => {}
+        }
+        log.records += 1;
+    }
+    Ok(log)
+}
+
+// Entries of a binary (or not yet migrated JSON) state file, without touching it
+pub(crate) fn read(path: &Path) -> std::io::Result<HashMap<String, CachedEntry>> {
+    let bytes = std::fs::read(path)?;
+    if bytes.starts_with(MAGIC) {
+        Ok(decode_log(&bytes)?.map)
+    } else if is_json(&bytes) {
+        Ok(serde_json::from_slice(&bytes)?)
+    } else {
+        Err(invalid("not a fadupes state file"))
+    }
+}
+
+fn is_json(bytes: &[u8]) -> bool {
+    bytes.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{')
+}
+
+// Resume state as an append-only binary log. A save appends the entries stored since the last
+// one; the file is rewritten from the live entries once superseded records pile up.
+#[derive(Debug)]
+pub struct BinaryStore {
+    path: PathBuf,
+    // Records currently in the file, live or superseded
+    records: Mutex<usize>,
+}
+
+impl BinaryStore {
+    // Reads an existing log, or migrates a JSON state found at the same path
+    pub fn open(path: &Path) -> std::io::Result<(Self, HashMap<String, CachedEntry>)> {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                return Ok((BinaryStore::new(path, 0), HashMap::new()));
+            }
+            Err(err) => return Err(err),
+        };
+
+        if bytes.starts_with(MAGIC) {
+            let log = decode_log(&bytes)?;
+            let store = BinaryStore::new(path, log.records);
+            if log.version < SCHEMA_VERSION {
+                // Records are appended in the current layout, so older files are rewritten first
+                store.compact(&log.map)?;
+            } else if log.valid_len < bytes.len() {
+                eprintln!(
+                    "Warning: dropping an incomplete record at the end of {}",
+                    path.display()
+                );
+                OpenOptions::new()
+                    .write(true)
+                    .open(path)?
+                    .set_len(log.valid_len as u64)?;
+            }
+            return Ok((store, log.map));
+        }
+
+        if is_json(&bytes) {
+            let map: HashMap<String, CachedEntry> = serde_json::from_slice(&bytes)?;
+            let mut backup = path.as_os_str().to_owned();
+            backup.push(".json");
+            std::fs::copy(path, &backup)?;
+            let store = BinaryStore::new(path, 0);
+            store.compact(&map)?;
+            eprintln!(
+                "Migrated {} entries in {} from JSON to the binary state format (original kept as {})",
+                map.len(),
+                path.display(),
+                backup.display()
+            );
+            return Ok((store, map));
+        }
+
+        Err(invalid("not a fadupes state file"))
+    }
+
+    // Current entries on disk, including what other processes appended since we opened it
+    pub fn reload(&self) -> std::io::Result<HashMap<String, CachedEntry>> {
+        let mut records = self.records.lock().unwrap();
+        let bytes = std::fs::read(&self.path)?;
+        let log = decode_log(&bytes)?;
+        // A process that died mid-append left a partial record; our appends must not follow it
+        if log.valid_len < bytes.len() {
+            OpenOptions::new()
+                .write(true)
+                .open(&self.path)?
+                .set_len(log.valid_len as u64)?;
+        }
+        *records = log.records;
+        Ok(log.map)
+    }
+
+    fn new(path: &Path, records: usize) -> Self {
+        BinaryStore {
+            path: path.to_path_buf(),
+            records: Mutex::new(records),
+        }
+    }
+
+    pub fn append(&self, entries: &[CachedEntry], removed: &[String]) -> std::io::Result<()> {
+        let mut records = self.records.lock().unwrap();
+        if let Some(parent) = self.path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let mut buf = Vec::new();
+        if file.metadata()?.len() < HEADER_LEN as u64 {
+            file.set_len(0)?;
+            buf = header();
+        }
+        for path in removed {
+            encode_remove(&mut buf, path);
+        }
+        for entry in entries {
+            encode_put(&mut buf, entry);
+        }
+        // One write per checkpoint keeps a crash down to at most one partial record
+        file.write_all(&buf)?;
+        file.sync_data()?;
+        *records += entries.len() + removed.len();
+        Ok(())
+    }
+
+    pub fn needs_compaction(&self, live: usize) -> bool {
+        let records = *self.records.lock().unwrap();
+        records >= COMPACT_MIN_RECORDS && records > live.saturating_mul(COMPACT_RATIO)
+    }
+
+    // Rewrite the log with exactly one record per live entry (temp file + rename)
+    pub fn compact(&self, map: &HashMap<String, CachedEntry>) -> std::io::Result<()> {
+        let mut records = self.records.lock().unwrap();
+        if let Some(parent) = self.path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let tmp_path = self.path.with_extension("tmp");
+        let mut out = std::io::BufWriter::new(File::create(&tmp_path)?);
+        out.write_all(&header())?;
+        let mut buf = Vec::new();
+        for entry in map.values() {
+            buf.clear();
+            encode_put(&mut buf, entry);
+            out.write_all(&buf)?;
+        }
+        let file = out.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        std::fs::rename(tmp_path, &self.path)?;
+        *records = map.len();
+        Ok(())
+    }
+}
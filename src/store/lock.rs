@@ -0,0 +1,84 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// Another run only holds the lock while it loads or saves, so a short wait normally gets it
+const LOCK_GRACE: Duration = Duration::from_secs(10);
+const LOCK_POLL: Duration = Duration::from_millis(100);
+
+// Advisory lock on `<state>.lock`, held while a process reads or writes the state file.
+// The sidecar file is never deleted: removing a flock'ed file lets two processes lock
+// different inodes under the same name.
+#[derive(Debug)]
+pub struct StateLock {
+    _file: File,
+}
+
+fn lock_path(state_path: &Path) -> PathBuf {
+    let mut path = state_path.as_os_str().to_owned();
+    path.push(".lock");
+    PathBuf::from(path)
+}
+
+impl StateLock {
+    // Waits up to LOCK_GRACE for another process to finish, or indefinitely with `wait`
+    pub fn acquire(state_path: &Path, wait: bool) -> std::io::Result<Self> {
+        let path = lock_path(state_path);
+        if let Some(parent) = path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(&path)?;
+
+        let deadline = Instant::now() + LOCK_GRACE;
+        loop {
+            match file.try_lock() {
+                Ok(()) => break,
+                Err(TryLockError::WouldBlock) if wait => {
+                    eprintln!(
+                        "Waiting for {} to be released by {}...",
+                        state_path.display(),
+                        holder(&path)
+                    );
+                    file.lock()?;
+                    break;
+                }
+                Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                    std::thread::sleep(LOCK_POLL);
+                }
+                Err(TryLockError::WouldBlock) => {
+                    return Err(std::io::Error::new(
+                        ErrorKind::WouldBlock,
+                        format!(
+                            "{} is in use by {}; pass --wait-lock to wait for it",
+                            state_path.display(),
+                            holder(&path)
+                        ),
+                    ));
+                }
+                Err(TryLockError::Error(err)) => return Err(err),
+            }
+        }
+
+        // Only informational, for the message of the next process that has to wait
+        file.set_len(0)?;
+        writeln!(file, "{}", std::process::id())?;
+        Ok(StateLock { _file: file })
+    }
+}
+
+fn holder(lock_path: &Path) -> String {
+    match std::fs::read_to_string(lock_path) {
+        Ok(pid) if !pid.trim().is_empty() => {
+            format!("another fadupes process (pid {})", pid.trim())
+        }
+        _ => "another fadupes process".to_string(),
+    }
+}
@@ -0,0 +1,453 @@
+use crate::actions::now_secs;
+use crate::lossless::{LosslessCheck, Verdict};
+use crate::run::RunInfo;
+use crate::{AudioFile, CachedEntry, ChannelStats, FileIdentity};
+use rusqlite::{Connection, params};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+// Migrations run in order; PRAGMA user_version records how many have been applied
+const MIGRATIONS: &[&str] = &[
+    SCHEMA_V1, SCHEMA_V2, SCHEMA_V3, SCHEMA_V4, SCHEMA_V5, SCHEMA_V6,
+];
+
+const SCHEMA_V1: &str = r#"
+CREATE TABLE files (
+    path TEXT PRIMARY KEY,
+    file_name TEXT NOT NULL,
+    file_size INTEGER NOT NULL,
+    modified_secs INTEGER NOT NULL,
+    updated_secs INTEGER NOT NULL
+);
+CREATE TABLE metrics (
+    path TEXT PRIMARY KEY REFERENCES files(path) ON DELETE CASCADE,
+    total_samples INTEGER NOT NULL,
+    sample_rate INTEGER NOT NULL,
+    bit_depth INTEGER NOT NULL,
+    channels INTEGER NOT NULL,
+    peak_level REAL NOT NULL,
+    rms_db_level REAL NOT NULL
+);
+CREATE TABLE tags (
+    path TEXT NOT NULL REFERENCES files(path) ON DELETE CASCADE,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (path, key)
+);
+CREATE TABLE scans (
+    id INTEGER PRIMARY KEY,
+    version TEXT NOT NULL,
+    started_secs INTEGER NOT NULL,
+    finished_secs INTEGER,
+    inputs TEXT NOT NULL,
+    options TEXT NOT NULL,
+    files_seen INTEGER,
+    files_processed INTEGER
+);
+"#;
+
+const SCHEMA_V2: &str = r#"
+ALTER TABLE files ADD COLUMN content_key INTEGER;
+"#;
+
+// File identity; all NULL for rows written before it was recorded
+const SCHEMA_V3: &str = r#"
+ALTER TABLE files ADD COLUMN modified_nanos INTEGER;
+ALTER TABLE files ADD COLUMN changed_secs INTEGER;
+ALTER TABLE files ADD COLUMN changed_nanos INTEGER;
+ALTER TABLE files ADD COLUMN inode INTEGER;
+ALTER TABLE files ADD COLUMN device INTEGER;
+"#;
+
+// Per-channel metrics; no rows for files scanned before they were recorded
+const SCHEMA_V4: &str = r#"
+CREATE TABLE channel_metrics (
+    path TEXT NOT NULL REFERENCES files(path) ON DELETE CASCADE,
+    channel INTEGER NOT NULL,
+    peak_level REAL NOT NULL,
+    rms_db_level REAL NOT NULL,
+    hash INTEGER NOT NULL,
+    abs_hash INTEGER NOT NULL,
+    PRIMARY KEY (path, channel)
+);
+"#;
+
+// Spectral lossless check; no row for files scanned before it was recorded
+const SCHEMA_V5: &str = r#"
+CREATE TABLE lossless_checks (
+    path TEXT PRIMARY KEY REFERENCES files(path) ON DELETE CASCADE,
+    verdict TEXT NOT NULL,
+    cutoff_hz INTEGER NOT NULL,
+    padded_bits INTEGER NOT NULL
+);
+"#;
+
+// Effective bit depth and sample rate; NULL for rows written before they were recorded
+const SCHEMA_V6: &str = r#"
+ALTER TABLE metrics ADD COLUMN effective_bit_depth INTEGER;
+ALTER TABLE metrics ADD COLUMN effective_sample_rate INTEGER;
+"#;
+
+fn db_err(err: rusqlite::Error) -> std::io::Error {
+    std::io::Error::other(err)
+}
+
+// Resume state kept in an embedded database. Rows are upserted per checkpoint, so a save costs
+// as much as the files scanned since the last one rather than the size of the whole library.
+#[derive(Debug)]
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+    // Row in `scans` describing the current run, once begin_scan was called
+    scan_id: Mutex<Option<i64>>,
+}
+
+impl SqliteStore {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            std::fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path).map_err(db_err)?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL; PRAGMA foreign_keys = ON;",
+        )
+        .map_err(db_err)?;
+        migrate(&conn)?;
+
+        Ok(SqliteStore {
+            conn: Mutex::new(conn),
+            scan_id: Mutex::new(None),
+        })
+    }
+
+    pub fn load_all(&self) -> std::io::Result<HashMap<String, CachedEntry>> {
+        let conn = self.conn.lock().unwrap();
+        let mut map = HashMap::new();
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT f.path, f.file_name, f.file_size, f.modified_secs, m.total_samples,
+                        m.sample_rate, m.bit_depth, m.channels, m.peak_level, m.rms_db_level,
+                        f.content_key, f.modified_nanos, f.changed_secs, f.changed_nanos, f.inode,
+                        f.device, m.effective_bit_depth, m.effective_sample_rate
+                 FROM files f JOIN metrics m ON m.path = f.path",
+            )
+            .map_err(db_err)?;
+        let rows = stmt
+            .query_map([], |row| {
+                let file_size = row.get::<_, i64>(2)? as u64;
+                let modified_secs = row.get::<_, i64>(3)? as u64;
+                Ok(CachedEntry {
+                    audio_file: AudioFile {
+                        file_path: row.get(0)?,
+                        file_name: row.get(1)?,
+                        total_samples: row.get::<_, i64>(4)? as u64,
+                        sample_rate: row.get(5)?,
+                        bit_depth: row.get(6)?,
+                        channels: row.get(7)?,
+                        // Stored widened to REAL; f32 -> f64 -> f32 is lossless so group keys survive
+                        peak_level: row.get::<_, f64>(8)? as f32,
+                        rms_db_level: row.get(9)?,
+                        file_size,
+                        modified_secs,
+                        tags: Default::default(),
+                        channel_stats: Vec::new(),
+                        lossless: None,
+                        effective_bit_depth: row.get::<_, Option<u32>>(16)?.unwrap_or(0),
+                        effective_sample_rate: row.get::<_, Option<u32>>(17)?.unwrap_or(0),
+                        reference: false,
+                        root: String::new(),
+                    },
+                    file_size,
+                    modified_secs,
+                    // SQLite integers are signed; the key is stored bit for bit
+                    content_key: row.get::<_, Option<i64>>(10)?.map(|k| k as u64),
+                    identity: match row.get::<_, Option<u32>>(11)? {
+                        Some(modified_nanos) => Some(FileIdentity {
+                            modified_nanos,
+                            changed_secs: row.get(12)?,
+                            changed_nanos: row.get(13)?,
+                            inode: row.get::<_, i64>(14)? as u64,
+                            device: row.get::<_, i64>(15)? as u64,
+                        }),
+                        None => None,
+                    },
+                })
+            })
+            .map_err(db_err)?;
+        for entry in rows {
+            let entry = entry.map_err(db_err)?;
+            map.insert(entry.audio_file.file_path.clone(), entry);
+        }
+
+        let mut stmt = conn
+            .prepare("SELECT path, key, value FROM tags")
+            .map_err(db_err)?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })
+            .map_err(db_err)?;
+        for row in rows {
+            let (path, key, value) = row.map_err(db_err)?;
+            if let Some(entry) = map.get_mut(&path) {
+                entry.audio_file.tags.insert(key, value);
+            }
+        }
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT path, peak_level, rms_db_level, hash, abs_hash FROM channel_metrics
+                 ORDER BY path, channel",
+            )
+            .map_err(db_err)?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    ChannelStats {
+                        peak_level: row.get::<_, f64>(1)? as f32,
+                        rms_db_level: row.get(2)?,
+                        hash: row.get::<_, i64>(3)? as u64,
+                        abs_hash: row.get::<_, i64>(4)? as u64,
+                    },
+                ))
+            })
+            .map_err(db_err)?;
+        for row in rows {
+            let (path, stats) = row.map_err(db_err)?;
+            if let Some(entry) = map.get_mut(&path) {
+                entry.audio_file.channel_stats.push(stats);
+            }
+        }
+
+        let mut stmt = conn
+            .prepare("SELECT path, verdict, cutoff_hz, padded_bits FROM lossless_checks")
+            .map_err(db_err)?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, u32>(2)?,
+                    row.get::<_, u32>(3)?,
+                ))
+            })
+            .map_err(db_err)?;
+        for row in rows {
+            let (path, verdict, cutoff_hz, padded_bits) = row.map_err(db_err)?;
+            // A verdict this version does not know is dropped: the file is checked again
+            if let (Some(entry), Some(verdict)) = (map.get_mut(&path), Verdict::from_name(&verdict))
+            {
+                entry.audio_file.lossless = Some(LosslessCheck {
+                    verdict,
+                    cutoff_hz,
+                    padded_bits,
+                });
+            }
+        }
+
+        Ok(map)
+    }
+
+    // Insert or replace `entries` and delete `removed` paths in a single transaction
+    pub fn apply_changes(
+        &self,
+        entries: &[CachedEntry],
+        removed: &[String],
+    ) -> std::io::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(db_err)?;
+        let updated_secs = now_secs() as i64;
+        {
+            let mut file_stmt = tx
+                .prepare_cached(
+                    "INSERT INTO files (path, file_name, file_size, modified_secs, updated_secs, content_key,
+                                        modified_nanos, changed_secs, changed_nanos, inode, device)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+                     ON CONFLICT(path) DO UPDATE SET
+                        file_name = excluded.file_name,
+                        file_size = excluded.file_size,
+                        modified_secs = excluded.modified_secs,
+                        updated_secs = excluded.updated_secs,
+                        content_key = excluded.content_key,
+                        modified_nanos = excluded.modified_nanos,
+                        changed_secs = excluded.changed_secs,
+                        changed_nanos = excluded.changed_nanos,
+                        inode = excluded.inode,
+                        device = excluded.device",
+                )
+                .map_err(db_err)?;
+            let mut metrics_stmt = tx
+                .prepare_cached(
+                    "INSERT OR REPLACE INTO metrics
+                        (path, total_samples, sample_rate, bit_depth, channels, peak_level, rms_db_level,
+                         effective_bit_depth, effective_sample_rate)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                )
+                .map_err(db_err)?;
+            let mut clear_tags = tx
+                .prepare_cached("DELETE FROM tags WHERE path = ?1")
+                .map_err(db_err)?;
+            let mut tag_stmt = tx
+                .prepare_cached("INSERT INTO tags (path, key, value) VALUES (?1, ?2, ?3)")
+                .map_err(db_err)?;
+            let mut clear_channels = tx
+                .prepare_cached("DELETE FROM channel_metrics WHERE path = ?1")
+                .map_err(db_err)?;
+            let mut channel_stmt = tx
+                .prepare_cached(
+                    "INSERT INTO channel_metrics (path, channel, peak_level, rms_db_level, hash, abs_hash)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                )
+                .map_err(db_err)?;
+            let mut clear_lossless = tx
+                .prepare_cached("DELETE FROM lossless_checks WHERE path = ?1")
+                .map_err(db_err)?;
+            let mut lossless_stmt = tx
+                .prepare_cached(
+                    "INSERT INTO lossless_checks (path, verdict, cutoff_hz, padded_bits)
+                     VALUES (?1, ?2, ?3, ?4)",
+                )
+                .map_err(db_err)?;
+            // Metrics, tags, channel metrics and lossless checks follow through ON DELETE CASCADE
+            let mut delete_stmt = tx
+                .prepare_cached("DELETE FROM files WHERE path = ?1")
+                .map_err(db_err)?;
+
+            for path in removed {
+                delete_stmt.execute([path]).map_err(db_err)?;
+            }
+
+            for entry in entries {
+                let file = &entry.audio_file;
+                let identity = entry.identity;
+                file_stmt
+                    .execute(params![
+                        file.file_path,
+                        file.file_name,
+                        entry.file_size as i64,
+                        entry.modified_secs as i64,
+                        updated_secs,
+                        entry.content_key.map(|k| k as i64),
+                        identity.map(|id| id.modified_nanos),
+                        identity.map(|id| id.changed_secs),
+                        identity.map(|id| id.changed_nanos),
+                        identity.map(|id| id.inode as i64),
+                        identity.map(|id| id.device as i64),
+                    ])
+                    .map_err(db_err)?;
+                metrics_stmt
+                    .execute(params![
+                        file.file_path,
+                        file.total_samples as i64,
+                        file.sample_rate,
+                        file.bit_depth,
+                        file.channels,
+                        f64::from(file.peak_level),
+                        file.rms_db_level,
+                        // 0 (not recorded) stays NULL
+                        (file.effective_bit_depth != 0).then_some(file.effective_bit_depth),
+                        (file.effective_sample_rate != 0).then_some(file.effective_sample_rate),
+                    ])
+                    .map_err(db_err)?;
+                clear_tags.execute([&file.file_path]).map_err(db_err)?;
+                for (key, value) in &file.tags {
+                    tag_stmt
+                        .execute(params![file.file_path, key, value])
+                        .map_err(db_err)?;
+                }
+                clear_channels.execute([&file.file_path]).map_err(db_err)?;
+                for (channel, stats) in file.channel_stats.iter().enumerate() {
+                    channel_stmt
+                        .execute(params![
+                            file.file_path,
+                            channel as i64,
+                            f64::from(stats.peak_level),
+                            stats.rms_db_level,
+                            stats.hash as i64,
+                            stats.abs_hash as i64,
+                        ])
+                        .map_err(db_err)?;
+                }
+                clear_lossless.execute([&file.file_path]).map_err(db_err)?;
+                if let Some(check) = file.lossless {
+                    lossless_stmt
+                        .execute(params![
+                            file.file_path,
+                            check.verdict.name(),
+                            check.cutoff_hz,
+                            check.padded_bits,
+                        ])
+                        .map_err(db_err)?;
+                }
+            }
+        }
+        tx.commit().map_err(db_err)
+    }
+
+    // Start a row in the scan history; finish_scan fills in the totals
+    pub fn begin_scan(&self, run: &RunInfo) -> std::io::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO scans (version, started_secs, inputs, options) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                run.version,
+                run.started_secs as i64,
+                serde_json::to_string(&run.inputs)?,
+                serde_json::to_string(&run.options)?,
+            ],
+        )
+        .map_err(db_err)?;
+        *self.scan_id.lock().unwrap() = Some(conn.last_insert_rowid());
+        Ok(())
+    }
+
+    pub fn finish_scan(&self, files_seen: usize, files_processed: usize) -> std::io::Result<()> {
+        let Some(id) = *self.scan_id.lock().unwrap() else {
+            return Ok(());
+        };
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE scans SET finished_secs = ?1, files_seen = ?2, files_processed = ?3 WHERE id = ?4",
+            params![
+                now_secs() as i64,
+                files_seen as i64,
+                files_processed as i64,
+                id
+            ],
+        )
+        .map_err(db_err)?;
+        Ok(())
+    }
+}
+
+fn migrate(conn: &Connection) -> std::io::Result<()> {
+    let version = conn
+        .pragma_query_value(None, "user_version", |row| row.get::<_, i64>(0))
+        .map_err(db_err)? as usize;
+    if version > MIGRATIONS.len() {
+        // Unsupported, not a broken database: the caller must not replace it
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!(
+                "database schema version {version} is newer than this fadupes understands ({})",
+                MIGRATIONS.len()
+            ),
+        ));
+    }
+    for (idx, sql) in MIGRATIONS.iter().enumerate().skip(version) {
+        conn.execute_batch(&format!(
+            "BEGIN; {sql} PRAGMA user_version = {}; COMMIT;",
+            idx + 1
+        ))
+        .map_err(db_err)?;
+    }
+    Ok(())
+}
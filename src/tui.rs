@@ -0,0 +1,399 @@
+use crate::actions::{ActionPlan, Decision, KeepPolicy};
+use crate::groups::DuplicateGroup;
+use crate::{format_size_bytes, format_unix_time};
+use ratatui::DefaultTerminal;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{
+    Block, Borders, Cell, List, ListItem, ListState, Paragraph, Row, Table, TableState, Wrap,
+};
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+
+// What the user asked for when leaving the review screen
+pub enum ReviewOutcome {
+    Execute(ActionPlan),
+    Exported(PathBuf),
+    Quit,
+}
+
+#[derive(PartialEq, Eq)]
+enum Focus {
+    Groups,
+    Files,
+}
+
+struct App {
+    plan: ActionPlan,
+    policy: KeepPolicy,
+    export_path: PathBuf,
+    focus: Focus,
+    group_state: ListState,
+    file_state: TableState,
+    status: String,
+    confirm_execute: bool,
+}
+
+// Interactive review of duplicate groups. Needs a real terminal on both stdin and stdout.
+pub fn review(
+    groups: &[DuplicateGroup],
+    policy: KeepPolicy,
+    export_path: &Path,
+) -> std::io::Result<ReviewOutcome> {
+    if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        return Err(std::io::Error::other(
+            "interactive review needs a terminal on stdin and stdout",
+        ));
+    }
+
+    let mut app = App {
+        plan: ActionPlan::new(groups),
+        policy,
+        export_path: export_path.to_path_buf(),
+        focus: Focus::Groups,
+        group_state: ListState::default().with_selected(Some(0)),
+        file_state: TableState::default().with_selected(Some(0)),
+        status: String::new(),
+        confirm_execute: false,
+    };
+
+    let mut terminal = ratatui::try_init()?;
+    let result = app.run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+impl App {
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> std::io::Result<ReviewOutcome> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            if self.confirm_execute {
+                self.confirm_execute = false;
+                if matches!(key.code, KeyCode::Char('y') | KeyCode::Char('Y')) {
+                    return Ok(ReviewOutcome::Execute(std::mem::take(&mut self.plan)));
+                }
+                self.status = "Execution cancelled".to_string();
+                continue;
+            }
+
+            self.status.clear();
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(ReviewOutcome::Quit),
+                KeyCode::Tab | KeyCode::Left | KeyCode::Right => {
+                    self.focus = if self.focus == Focus::Groups {
+                        Focus::Files
+                    } else {
+                        Focus::Groups
+                    };
+                }
+                KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+                KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+                KeyCode::PageUp => self.move_group(-1),
+                KeyCode::PageDown => self.move_group(1),
+                KeyCode::Char(' ') => self.toggle_selected(),
+                KeyCode::Char('K') => self.keep_only_selected(),
+                KeyCode::Char('r') => self.reset_group(),
+                KeyCode::Char('p') => {
+                    self.policy = self.policy.next();
+                    self.status = format!("Keep policy: {}", self.policy.name());
+                }
+                KeyCode::Char('a') => {
+                    let policy = self.policy;
+                    if let Some(group) = self.current_group_mut() {
+                        group.apply_policy(policy);
+                    }
+                }
+                KeyCode::Char('A') => {
+                    let before = self.plan.groups.iter().filter(|g| !g.reviewed).count();
+                    self.plan.apply_policy_to_unreviewed(self.policy);
+                    self.status = format!(
+                        "Applied '{}' to {before} remaining groups",
+                        self.policy.name()
+                    );
+                }
+                KeyCode::Char('e') => {
+                    self.plan.save_json(&self.export_path)?;
+                    return Ok(ReviewOutcome::Exported(self.export_path.clone()));
+                }
+                KeyCode::Char('x') => {
+                    if self.plan.pending_deletes() == 0 {
+                        self.status = "Nothing marked for deletion".to_string();
+                    } else {
+                        self.confirm_execute = true;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn group_index(&self) -> usize {
+        self.group_state.selected().unwrap_or(0)
+    }
+
+    fn file_index(&self) -> usize {
+        self.file_state.selected().unwrap_or(0)
+    }
+
+    fn current_group_mut(&mut self) -> Option<&mut crate::actions::GroupPlan> {
+        let idx = self.group_index();
+        self.plan.groups.get_mut(idx)
+    }
+
+    fn move_group(&mut self, delta: isize) {
+        let len = self.plan.groups.len();
+        if len == 0 {
+            return;
+        }
+        let next = (self.group_index() as isize + delta).clamp(0, len as isize - 1) as usize;
+        self.group_state.select(Some(next));
+        self.file_state.select(Some(0));
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        if self.focus == Focus::Groups {
+            self.move_group(delta);
+            return;
+        }
+        let len = self
+            .plan
+            .groups
+            .get(self.group_index())
+            .map(|g| g.files.len())
+            .unwrap_or(0);
+        if len == 0 {
+            return;
+        }
+        let next = (self.file_index() as isize + delta).clamp(0, len as isize - 1) as usize;
+        self.file_state.select(Some(next));
+    }
+
+    fn toggle_selected(&mut self) {
+        let file_idx = self.file_index();
+        if let Some(group) = self.current_group_mut()
+            && let Some(planned) = group.files.get_mut(file_idx)
+            && !planned.file.reference
+        {
+            planned.decision = match planned.decision {
+                Decision::Keep => Decision::Delete,
+                Decision::Delete => Decision::Keep,
+            };
+            group.reviewed = true;
+        }
+    }
+
+    fn keep_only_selected(&mut self) {
+        let file_idx = self.file_index();
+        if let Some(group) = self.current_group_mut() {
+            for (idx, planned) in group.files.iter_mut().enumerate() {
+                planned.decision = if idx == file_idx || planned.file.reference {
+                    Decision::Keep
+                } else {
+                    Decision::Delete
+                };
+            }
+            group.reviewed = true;
+            group.chosen = Some(file_idx);
+        }
+    }
+
+    fn reset_group(&mut self) {
+        if let Some(group) = self.current_group_mut() {
+            for planned in group.files.iter_mut() {
+                planned.decision = Decision::Keep;
+            }
+            group.reviewed = false;
+            group.chosen = None;
+        }
+    }
+
+    fn draw(&mut self, frame: &mut ratatui::Frame) {
+        let [main, details, footer] = Layout::vertical([
+            Constraint::Min(8),
+            Constraint::Length(8),
+            Constraint::Length(2),
+        ])
+        .areas(frame.area());
+        let [groups_area, files_area] =
+            Layout::horizontal([Constraint::Percentage(35), Constraint::Percentage(65)])
+                .areas(main);
+
+        self.draw_groups(frame, groups_area);
+        self.draw_files(frame, files_area);
+        self.draw_details(frame, details);
+        self.draw_footer(frame, footer);
+    }
+
+    fn focus_block(&self, title: String, focused: bool) -> Block<'static> {
+        let style = if focused {
+            Style::default().fg(Color::Cyan)
+        } else {
+            Style::default()
+        };
+        Block::default()
+            .borders(Borders::ALL)
+            .border_style(style)
+            .title(title)
+    }
+
+    fn draw_groups(&mut self, frame: &mut ratatui::Frame, area: Rect) {
+        let items: Vec<ListItem> = self
+            .plan
+            .groups
+            .iter()
+            .enumerate()
+            .map(|(idx, group)| {
+                let deletes = group
+                    .files
+                    .iter()
+                    .filter(|p| p.decision == Decision::Delete)
+                    .count();
+                let first = &group.files[0].file;
+                let label = match first.tag_label() {
+                    label if !label.is_empty() => label,
+                    _ => Path::new(&first.file_path)
+                        .file_name()
+                        .map(|n| n.to_string_lossy().to_string())
+                        .unwrap_or_default(),
+                };
+                let marker = if group.reviewed { "✓" } else { " " };
+                let style = if deletes > 0 {
+                    Style::default().fg(Color::Yellow)
+                } else {
+                    Style::default()
+                };
+                ListItem::new(format!(
+                    "{marker}{:>5} {}x {} {label}",
+                    idx + 1,
+                    group.files.len(),
+                    if deletes > 0 {
+                        format!("(-{deletes})")
+                    } else {
+                        String::new()
+                    }
+                ))
+                .style(style)
+            })
+            .collect();
+
+        let reviewed = self.plan.groups.iter().filter(|g| g.reviewed).count();
+        let title = format!(" Groups {reviewed}/{} reviewed ", self.plan.groups.len());
+        let list = List::new(items)
+            .block(self.focus_block(title, self.focus == Focus::Groups))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, area, &mut self.group_state);
+    }
+
+    fn draw_files(&mut self, frame: &mut ratatui::Frame, area: Rect) {
+        let rows: Vec<Row> = self
+            .plan
+            .groups
+            .get(self.group_index())
+            .map(|group| {
+                group
+                    .files
+                    .iter()
+                    .map(|planned| {
+                        let file = &planned.file;
+                        let (mark, style) = match planned.decision {
+                            Decision::Keep if file.reference => {
+                                ("REF", Style::default().fg(Color::Cyan))
+                            }
+                            Decision::Keep => ("KEEP", Style::default().fg(Color::Green)),
+                            Decision::Delete => ("DEL", Style::default().fg(Color::Red)),
+                        };
+                        Row::new(vec![
+                            Cell::from(mark).style(style),
+                            Cell::from(file.format()),
+                            Cell::from(format!("{}bit", file.bit_depth)),
+                            Cell::from(format!("{}Hz", file.sample_rate)),
+                            Cell::from(format_size_bytes(file.file_size)),
+                            Cell::from(format_unix_time(file.modified_secs)),
+                            Cell::from(file.file_path.clone()),
+                        ])
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(4),
+                Constraint::Length(5),
+                Constraint::Length(6),
+                Constraint::Length(8),
+                Constraint::Length(9),
+                Constraint::Length(19),
+                Constraint::Min(10),
+            ],
+        )
+        .header(
+            Row::new(vec!["", "Fmt", "Depth", "Rate", "Size", "Modified", "Path"])
+                .style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .block(self.focus_block(" Files ".to_string(), self.focus == Focus::Files))
+        .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, area, &mut self.file_state);
+    }
+
+    // Tags of the highlighted file; values that differ from the group's first file are highlighted
+    fn draw_details(&self, frame: &mut ratatui::Frame, area: Rect) {
+        let mut lines = Vec::new();
+        if let Some(group) = self.plan.groups.get(self.group_index())
+            && let Some(planned) = group.files.get(self.file_index())
+        {
+            let reference = &group.files[0].file.tags;
+            if planned.file.tags.is_empty() {
+                lines.push(Line::from("(no tags)"));
+            }
+            for (key, value) in &planned.file.tags {
+                let style = if reference.get(key) == Some(value) {
+                    Style::default()
+                } else {
+                    Style::default().fg(Color::Yellow)
+                };
+                lines.push(Line::from(vec![
+                    Span::styled(
+                        format!("{key}: "),
+                        Style::default().add_modifier(Modifier::BOLD),
+                    ),
+                    Span::styled(value.clone(), style),
+                ]));
+            }
+        }
+
+        let paragraph = Paragraph::new(lines)
+            .wrap(Wrap { trim: true })
+            .block(Block::default().borders(Borders::ALL).title(" Tags "));
+        frame.render_widget(paragraph, area);
+    }
+
+    fn draw_footer(&self, frame: &mut ratatui::Frame, area: Rect) {
+        let summary = format!(
+            "policy: {} | {} to delete, {} reclaimable",
+            self.policy.name(),
+            self.plan.pending_deletes(),
+            format_size_bytes(self.plan.reclaimable_bytes())
+        );
+        let prompt = if self.confirm_execute {
+            format!("Delete {} files now? [y/N]", self.plan.pending_deletes())
+        } else if !self.status.is_empty() {
+            self.status.clone()
+        } else {
+            "tab focus  space toggle  K keep only  r reset  p policy  a apply  A apply to rest  x execute  e export  q quit".to_string()
+        };
+        let paragraph = Paragraph::new(vec![Line::from(summary), Line::from(prompt)]);
+        frame.render_widget(paragraph, area);
+    }
+}
@@ -0,0 +1,127 @@
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+// What `fadupes watch` has to act on. Paths are not filtered by type; the scanner does that.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    // Written and closed, or moved into a watched directory
+    Updated(PathBuf),
+    // Deleted or moved away; a directory stands for everything below it
+    Removed(PathBuf),
+    // The kernel queue overflowed and events were lost, so only a rescan is reliable
+    Rescan,
+}
+
+// Recursive directory watcher on top of inotify (which only watches single directories)
+pub struct DirWatcher {
+    inotify: Inotify,
+    dirs: HashMap<WatchDescriptor, PathBuf>,
+    buffer: Vec<u8>,
+}
+
+// Files are picked up once closed after writing, so rips in progress are not decoded half-written
+fn watch_mask() -> WatchMask {
+    WatchMask::CLOSE_WRITE
+        | WatchMask::CREATE
+        | WatchMask::MOVED_TO
+        | WatchMask::MOVED_FROM
+        | WatchMask::DELETE
+}
+
+impl DirWatcher {
+    pub fn new() -> std::io::Result<Self> {
+        Ok(DirWatcher {
+            inotify: Inotify::init()?,
+            dirs: HashMap::new(),
+            buffer: vec![0; 64 * 1024],
+        })
+    }
+
+    pub fn watched_dirs(&self) -> usize {
+        self.dirs.len()
+    }
+
+    // Watch `root` and every directory below it. Returns the files found on the way, which may
+    // have been written before their directory was watched.
+    pub fn add_tree(&mut self, root: &Path) -> std::io::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for entry in WalkDir::new(root).into_iter().filter_map(|e| e.ok()) {
+            if entry.file_type().is_dir() {
+                let wd = match self.inotify.watches().add(entry.path(), watch_mask()) {
+                    Ok(wd) => wd,
+                    // Gone again before we got to it
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                    // ENOSPC: out of watches, not out of disk space
+                    Err(err) if err.raw_os_error() == Some(28) => {
+                        return Err(std::io::Error::other(format!(
+                            "cannot watch {}: inotify watch limit reached (raise fs.inotify.max_user_watches)",
+                            entry.path().display()
+                        )));
+                    }
+                    Err(err) => return Err(err),
+                };
+                // Re-adding a directory that was renamed returns its old descriptor: update the path
+                self.dirs.insert(wd, entry.path().to_path_buf());
+            } else {
+                files.push(entry.into_path());
+            }
+        }
+        Ok(files)
+    }
+
+    // Stop watching a directory that went away and everything below it
+    fn remove_tree(&mut self, root: &Path) {
+        let gone: Vec<WatchDescriptor> = self
+            .dirs
+            .iter()
+            .filter(|(_, dir)| dir.starts_with(root))
+            .map(|(wd, _)| wd.clone())
+            .collect();
+        for wd in gone {
+            self.dirs.remove(&wd);
+            // Fails when the kernel already dropped it with the directory
+            let _ = self.inotify.watches().remove(wd);
+        }
+    }
+
+    // Blocks until something changes and returns everything that arrived together
+    pub fn next_changes(&mut self) -> std::io::Result<Vec<Change>> {
+        let mut raw = Vec::new();
+        for event in self.inotify.read_events_blocking(&mut self.buffer)? {
+            if event.mask.contains(EventMask::Q_OVERFLOW) {
+                raw.push((event.mask, None));
+                continue;
+            }
+            if event.mask.contains(EventMask::IGNORED) {
+                self.dirs.remove(&event.wd);
+                continue;
+            }
+            let (Some(dir), Some(name)) = (self.dirs.get(&event.wd), event.name) else {
+                continue;
+            };
+            raw.push((event.mask, Some(dir.join(name))));
+        }
+
+        let mut changes = Vec::new();
+        for (mask, path) in raw {
+            let Some(path) = path else {
+                changes.push(Change::Rescan);
+                continue;
+            };
+            let is_dir = mask.contains(EventMask::ISDIR);
+            if mask.intersects(EventMask::DELETE | EventMask::MOVED_FROM) {
+                if is_dir {
+                    self.remove_tree(&path);
+                }
+                changes.push(Change::Removed(path));
+            } else if is_dir && mask.intersects(EventMask::CREATE | EventMask::MOVED_TO) {
+                changes.extend(self.add_tree(&path)?.into_iter().map(Change::Updated));
+            } else if !is_dir && mask.intersects(EventMask::CLOSE_WRITE | EventMask::MOVED_TO) {
+                changes.push(Change::Updated(path));
+            }
+        }
+        Ok(changes)
+    }
+}